@@ -0,0 +1,7 @@
+-- Add migration script here
+create table sessions(
+    session_key text primary key,
+    state jsonb not null,
+    expires_at timestamptz not null
+);
+create index sessions_expires_at_idx on sessions (expires_at);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Seed the initial admin account. Password: everythinghastostartsomewhere
+insert into users (user_id, username, password_hash)
+values (
+    'ddf8994f-d522-4659-8d02-c1d479057be6',
+    'admin',
+    '$argon2id$v=19$m=15000,t=2,p=1$Tjmqifj7ngvanX8UWWJGtw$Y3zMzXIPDJN8Lqb8wjUlljuuzF9DTwPxgZ1+dBPkJpM'
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+create type header_pair as (
+    name text,
+    value bytea
+);
+
+create table idempotency (
+    user_id uuid not null references users(user_id),
+    idempotency_key text not null,
+    response_status_code smallint,
+    response_headers header_pair[],
+    response_body bytea,
+    created_at timestamptz not null,
+    primary key(user_id, idempotency_key)
+);
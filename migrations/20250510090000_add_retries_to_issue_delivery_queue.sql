@@ -0,0 +1,5 @@
+-- Add migration script here
+alter table issue_delivery_queue
+    add column n_retries smallint not null default 0;
+alter table issue_delivery_queue
+    add column execute_after timestamptz not null default now();
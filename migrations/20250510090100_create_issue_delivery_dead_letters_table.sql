@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Deliveries that failed permanently or ran out of retries.
+create table issue_delivery_dead_letters (
+    newsletter_issue_id uuid not null
+        references newsletter_issues (newsletter_issue_id),
+    subscriber_email text not null,
+    n_attempts smallint not null,
+    last_error text not null,
+    failed_at timestamptz not null,
+    primary key(newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Deliveries the email provider accepted, with the id it assigned.
+create table issue_deliveries (
+    newsletter_issue_id uuid not null
+        references newsletter_issues (newsletter_issue_id),
+    subscriber_email text not null,
+    provider_message_id text,
+    delivered_at timestamptz not null,
+    primary key(newsletter_issue_id, subscriber_email)
+);
+create index issue_deliveries_provider_message_id_idx
+    on issue_deliveries (provider_message_id);
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Raw webhook events (bounces, spam complaints, deliveries) from the
+-- email provider, kept for auditing.
+create table email_provider_events (
+    event_id uuid primary key,
+    record_type text not null,
+    provider_message_id text,
+    recipient text,
+    payload jsonb not null,
+    received_at timestamptz not null
+);
+create index email_provider_events_provider_message_id_idx
+    on email_provider_events (provider_message_id);
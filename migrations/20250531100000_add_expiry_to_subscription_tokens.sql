@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Tokens issued before this migration get a one day grace period; after that
+-- subscribers have to ask for a fresh confirmation email.
+alter table subscription_tokens
+    add column created_at timestamptz not null default now(),
+    add column expires_at timestamptz not null default now() + interval '1 day';
+alter table subscription_tokens
+    alter column created_at drop default,
+    alter column expires_at drop default;
+create index subscription_tokens_subscriber_id_idx
+    on subscription_tokens (subscriber_id);
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Only a SHA-256 digest of each confirmation token is kept from now on.
+-- Hashing the outstanding ones keeps the links already sent working.
+update subscription_tokens
+    set subscription_token = encode(
+        sha256(convert_to(subscription_token, 'UTF8')),
+        'hex'
+    );
+alter table subscription_tokens
+    rename column subscription_token to subscription_token_hash;
@@ -0,0 +1,2 @@
+-- Add migration script here
+alter table subscription_tokens add column consumed_at timestamptz null;
@@ -0,0 +1,6 @@
+-- Add migration script here
+create table rate_limit_buckets (
+    key text primary key,
+    tokens double precision not null,
+    updated_at timestamptz not null
+);
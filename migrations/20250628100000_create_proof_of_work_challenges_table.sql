@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Challenges that have already been spent on a signup, kept until they
+-- expire so that a solved challenge cannot be replayed.
+create table proof_of_work_challenges (
+    nonce text primary key,
+    expires_at timestamptz not null
+);
@@ -0,0 +1,41 @@
+-- Add migration script here
+-- Addresses are told apart by their normalised form, so that
+-- `Foo@Example.com` and `foo@example.com` are the same subscriber.
+-- Internationalised domains already on file are only lowercased here;
+-- the application punycode-encodes them from now on.
+begin;
+    alter table subscriptions add column email_normalized text null;
+
+    update subscriptions
+        set email_normalized = lower(trim(email));
+
+    -- Keep a single row per address: the most engaged one, then the oldest.
+    create temporary table duplicate_subscriptions on commit drop as
+        select id from (
+            select
+                id,
+                row_number() over (
+                    partition by email_normalized
+                    order by
+                        case status
+                            when 'confirmed' then 0
+                            when 'pending_confirmation' then 1
+                            else 2
+                        end,
+                        subscribed_at
+                ) as rank
+            from subscriptions
+        ) as ranked
+        where rank > 1;
+
+    delete from subscription_tokens
+        where subscriber_id in (select id from duplicate_subscriptions);
+    delete from subscriptions
+        where id in (select id from duplicate_subscriptions);
+
+    alter table subscriptions
+        alter column email_normalized set not null,
+        add constraint subscriptions_email_normalized_key
+            unique (email_normalized),
+        drop constraint subscriptions_email_key;
+commit;
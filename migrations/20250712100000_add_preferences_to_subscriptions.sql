@@ -0,0 +1,5 @@
+-- Add migration script here
+alter table subscriptions
+    add column email_format text not null default 'html'
+        check (email_format in ('html', 'text')),
+    add column paused_until timestamptz null;
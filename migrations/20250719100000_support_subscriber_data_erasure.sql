@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Erasing a subscriber takes their confirmation tokens with them.
+alter table subscription_tokens
+    drop constraint subscription_tokens_subscriber_id_fkey,
+    add constraint subscription_tokens_subscriber_id_fkey
+        foreign key (subscriber_id) references subscriptions (id)
+        on delete cascade;
+-- Addresses whose data was erased, kept only as a SHA-256 digest of the
+-- normalised address so that they are not signed up again behind their back.
+create table suppressed_emails (
+    email_hash text primary key,
+    suppressed_at timestamptz not null
+);
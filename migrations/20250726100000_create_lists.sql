@@ -0,0 +1,69 @@
+-- Add migration script here
+create table lists (
+    list_id uuid primary key,
+    slug text not null unique,
+    name text not null,
+    -- Fall back on the configured sender when not set.
+    sender_name text null,
+    sender_email text null,
+    confirmation_subject text not null,
+    confirmation_message text not null,
+    -- Where the unscoped `/subscriptions` URLs and publishing go.
+    is_default boolean not null default false,
+    created_at timestamptz not null
+);
+create unique index lists_is_default_idx on lists (is_default) where is_default;
+insert into lists (
+    list_id,
+    slug,
+    name,
+    confirmation_subject,
+    confirmation_message,
+    is_default,
+    created_at
+)
+values (
+    gen_random_uuid(),
+    'newsletter',
+    'Our newsletter',
+    'Email Confirmation',
+    'Welcome to our newsletter!',
+    true,
+    now()
+);
+
+-- `subscriptions` keeps one row per address; what it is subscribed to lives
+-- here. Everybody subscribed so far subscribed to the one list there was.
+create table list_subscriptions (
+    list_id uuid not null references lists (list_id),
+    subscriber_id uuid not null
+        references subscriptions (id) on delete cascade,
+    status text not null,
+    subscribed_at timestamptz not null,
+    primary key (list_id, subscriber_id)
+);
+create index list_subscriptions_subscriber_id_idx
+    on list_subscriptions (subscriber_id);
+insert into list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+select
+    (select list_id from lists where is_default),
+    id,
+    case
+        when status in ('pending_confirmation', 'confirmed') then status
+        else 'unsubscribed'
+    end,
+    subscribed_at
+from subscriptions;
+
+-- A confirmation link confirms the subscription to one list.
+alter table subscription_tokens
+    add column list_id uuid null references lists (list_id);
+update subscription_tokens
+    set list_id = (select list_id from lists where is_default);
+alter table subscription_tokens alter column list_id set not null;
+
+alter table newsletter_issues
+    add column list_id uuid null references lists (list_id);
+update newsletter_issues
+    set list_id = (select list_id from lists where is_default);
+alter table newsletter_issues alter column list_id set not null;
@@ -0,0 +1,22 @@
+-- Add migration script here
+-- The account seeded by 20250419151000_seed_user.sql has a password anyone
+-- can read in the repository. Drop it unless its password was changed since;
+-- admins are now created at deploy time with the `create_admin` binary.
+delete from idempotency
+where user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' and exists (
+    select 1 from users
+    where
+        user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' and
+        password_hash = '$argon2id$v=19$m=15000,t=2,p=1$Tjmqifj7ngvanX8UWWJGtw$Y3zMzXIPDJN8Lqb8wjUlljuuzF9DTwPxgZ1+dBPkJpM'
+);
+delete from sessions
+where state->>'user_id' = '"ddf8994f-d522-4659-8d02-c1d479057be6"' and exists (
+    select 1 from users
+    where
+        user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' and
+        password_hash = '$argon2id$v=19$m=15000,t=2,p=1$Tjmqifj7ngvanX8UWWJGtw$Y3zMzXIPDJN8Lqb8wjUlljuuzF9DTwPxgZ1+dBPkJpM'
+);
+delete from users
+where
+    user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' and
+    password_hash = '$argon2id$v=19$m=15000,t=2,p=1$Tjmqifj7ngvanX8UWWJGtw$Y3zMzXIPDJN8Lqb8wjUlljuuzF9DTwPxgZ1+dBPkJpM';
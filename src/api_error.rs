@@ -0,0 +1,211 @@
+//! The JSON error envelope shared by every route:
+//!
+//! ```json
+//! {"error": {"code": "...", "message": "...", "request_id": "...", "details": []}}
+//! ```
+use actix_web::{
+    HttpMessage, HttpRequest, HttpResponse,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::{
+        InternalError, JsonPayloadError, QueryPayloadError, UrlencodedError,
+    },
+    http::{
+        StatusCode,
+        header::{self, HeaderValue},
+    },
+    middleware::Next,
+};
+use tracing_actix_web::RequestId;
+
+/// What an error response needs to know about the request being served.
+#[derive(Clone)]
+struct RequestContext {
+    request_id: Option<String>,
+    accepts_json: bool,
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+/// Make the request id and `Accept` header available to
+/// `ResponseError::error_response`, which does not get to see the request.
+///
+/// Must run inside `TracingLogger`, which assigns the request id.
+pub async fn with_request_context(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let context = RequestContext {
+        request_id: req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.to_string()),
+        accepts_json: req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json")),
+    };
+    REQUEST_CONTEXT.scope(context, next.call(req)).await
+}
+
+/// Whether the client explicitly asked for JSON. Pages meant for browsers
+/// use it to decide between HTML and the envelope.
+pub fn client_accepts_json() -> bool {
+    REQUEST_CONTEXT
+        .try_with(|context| context.accepts_json)
+        .unwrap_or(false)
+}
+
+/// A validation failure tied to one field of the request.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Join field errors into a single line, for logs and `Display` impls.
+pub fn describe_field_errors(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[derive(serde::Serialize)]
+struct ErrorEnvelope<'a> {
+    error: ErrorBody<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+    request_id: Option<String>,
+    details: &'a [FieldError],
+}
+
+/// Render an error as the shared JSON envelope.
+///
+/// `message` is shown to clients as is: never pass it the `Display` of an
+/// unexpected error.
+pub fn error_response(
+    status: StatusCode,
+    code: &str,
+    message: &str,
+    details: &[FieldError],
+) -> HttpResponse {
+    let request_id = REQUEST_CONTEXT
+        .try_with(|context| context.request_id.clone())
+        .ok()
+        .flatten();
+    HttpResponse::build(status).json(ErrorEnvelope {
+        error: ErrorBody {
+            code,
+            message,
+            request_id,
+            details,
+        },
+    })
+}
+
+/// The envelope for errors we cannot tell clients anything about.
+pub fn internal_error_response() -> HttpResponse {
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "internal_error",
+        "An unexpected error occurred.",
+        &[],
+    )
+}
+
+/// The envelope for throttled requests, with a `Retry-After` header.
+pub fn too_many_requests_response(
+    message: &str,
+    retry_after: std::time::Duration,
+) -> HttpResponse {
+    let mut response = error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "too_many_requests",
+        message,
+        &[],
+    );
+    // Round up, so that retrying on time never gets throttled.
+    let seconds = retry_after.as_millis().div_ceil(1000) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+/// The envelope for failed `Basic` authentication, with the
+/// `WWW-Authenticate` challenge for `realm`.
+pub fn unauthorized_response(realm: &str) -> HttpResponse {
+    let mut response = error_response(
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+        "Authentication failed.",
+        &[],
+    );
+    let header_value =
+        HeaderValue::from_str(&format!(r#"Basic realm="{}""#, realm)).unwrap();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, header_value);
+    response
+}
+
+/// Report malformed JSON bodies through the envelope.
+pub fn json_error_handler(
+    err: JsonPayloadError,
+    _req: &HttpRequest,
+) -> actix_web::Error {
+    bad_request(err)
+}
+
+/// Report malformed form bodies through the envelope.
+pub fn form_error_handler(
+    err: UrlencodedError,
+    _req: &HttpRequest,
+) -> actix_web::Error {
+    bad_request(err)
+}
+
+/// Report malformed query strings through the envelope.
+pub fn query_error_handler(
+    err: QueryPayloadError,
+    _req: &HttpRequest,
+) -> actix_web::Error {
+    bad_request(err)
+}
+
+fn bad_request<E>(err: E) -> actix_web::Error
+where
+    E: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    let response = error_response(
+        StatusCode::BAD_REQUEST,
+        "bad_request",
+        &err.to_string(),
+        &[],
+    );
+    InternalError::from_response(err, response).into()
+}
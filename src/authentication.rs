@@ -0,0 +1,11 @@
+//! src/authentication.rs
+
+mod basic;
+mod middleware;
+mod password;
+
+pub use basic::{authenticate_basic, basic_authentication};
+pub use middleware::{UserId, reject_anonymous_users};
+pub use password::{
+    AuthError, Credentials, compute_password_hash, validate_credentials,
+};
@@ -0,0 +1,63 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use base64::Engine;
+use secrecy::SecretString;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{AuthError, Credentials, validate_credentials};
+
+/// Authenticate a request by its `Basic` credentials, recording who made it
+/// in the `username` and `user_id` fields of the current span.
+pub async fn authenticate_basic(
+    headers: &HeaderMap,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let credentials =
+        basic_authentication(headers).map_err(AuthError::InvalidCredentials)?;
+    tracing::Span::current()
+        .record("username", tracing::field::display(&credentials.username));
+
+    let user_id = validate_credentials(credentials, pool).await?;
+    tracing::Span::current()
+        .record("user_id", tracing::field::display(&user_id));
+    Ok(user_id)
+}
+
+/// Extract the credentials of an HTTP `Basic` `Authorization` header.
+pub fn basic_authentication(
+    headers: &HeaderMap,
+) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| {
+            anyhow::anyhow!("A username must be provided in 'Basic' auth.")
+        })?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| {
+            anyhow::anyhow!("A password must be provided in 'Basic' auth.")
+        })?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: SecretString::from(password),
+    })
+}
@@ -0,0 +1,64 @@
+//! Create an admin account, or reset its password if it already exists.
+//!
+//! The password is read from the first line of stdin, so that it never shows
+//! up in the shell history or the process list:
+//!
+//! ```bash
+//! cargo run --bin create_admin -- <username> < password.txt
+//! ```
+use std::io::BufRead;
+
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+use zero2prod::{
+    authentication::compute_password_hash, configuration::get_configuration,
+};
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let username = std::env::args()
+        .nth(1)
+        .context("Usage: create_admin <username>, password on stdin")?;
+
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .context("Failed to read the password from stdin.")?;
+    let password = SecretString::from(password.trim_end_matches(['\r', '\n']));
+    anyhow::ensure!(
+        password.expose_secret().chars().count() >= MIN_PASSWORD_LENGTH,
+        "The password must be at least {} characters long.",
+        MIN_PASSWORD_LENGTH
+    );
+
+    let configuration =
+        get_configuration().expect("Failed to read configuration.");
+    let mut connection =
+        PgConnection::connect_with(&configuration.database.with_db())
+            .await
+            .context("Failed to connect to Postgres.")?;
+
+    let password_hash = compute_password_hash(password)?;
+    sqlx::query!(
+        r#"
+        insert into users (user_id, username, password_hash)
+        values ($1, $2, $3)
+        on conflict (username) do update
+        set password_hash = excluded.password_hash
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret()
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to store the admin account.")?;
+
+    println!("Admin account '{}' is ready.", username);
+    Ok(())
+}
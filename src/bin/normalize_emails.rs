@@ -0,0 +1,28 @@
+//! Re-normalize the subscriber emails stored before the application started
+//! punycode-encoding internationalised domains.
+//!
+//! Run once after migrating; running it again does nothing:
+//!
+//! ```bash
+//! cargo run --bin normalize_emails
+//! ```
+use anyhow::Context;
+use sqlx::PgPool;
+use zero2prod::{
+    configuration::get_configuration,
+    email_normalization::normalize_stored_emails,
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let configuration =
+        get_configuration().expect("Failed to read configuration.");
+    let pool = PgPool::connect_with(configuration.database.with_db())
+        .await
+        .context("Failed to connect to Postgres.")?;
+
+    let n_normalized = normalize_stored_emails(&pool).await?;
+
+    println!("Re-normalized {} subscriber email(s).", n_normalized);
+    Ok(())
+}
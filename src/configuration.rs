@@ -3,13 +3,19 @@ use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::ConnectOptions;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
-use crate::domain::SubscriberEmail;
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailSender, OutboxClient, PostmarkClient, SmtpClient},
+};
 
 #[derive(Clone, serde::Deserialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub environment: Environment,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -18,6 +24,123 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: SecretString,
+    pub session_ttl_minutes: i64,
+    /// Where to send subscribers once they have confirmed, instead of
+    /// showing them our own page.
+    pub confirmation_redirect_url: Option<String>,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub proof_of_work: ProofOfWorkSettings,
+    #[serde(default)]
+    pub email_policy: EmailPolicySettings,
+}
+
+/// Limits on the endpoints that make us send confirmation emails.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackend,
+    /// Take the client address from `Forwarded`/`X-Forwarded-For`. Only
+    /// enable this behind a proxy that sets those headers itself.
+    pub trust_forwarded_headers: bool,
+    pub per_ip: TokenBucketSettings,
+    pub per_email: TokenBucketSettings,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            backend: RateLimitBackend::Memory,
+            trust_forwarded_headers: false,
+            per_ip: TokenBucketSettings {
+                capacity: 20,
+                refill_interval_seconds: 30,
+            },
+            per_email: TokenBucketSettings {
+                capacity: 5,
+                refill_interval_seconds: 600,
+            },
+        }
+    }
+}
+
+/// Where token buckets live.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    /// Per process: every instance enforces its own limits.
+    Memory,
+    /// Shared by every instance using the same database.
+    Postgres,
+}
+
+/// A bucket holds up to `capacity` requests and regains one every
+/// `refill_interval_seconds`.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct TokenBucketSettings {
+    pub capacity: u32,
+    pub refill_interval_seconds: u64,
+}
+
+/// The challenge signups must solve before we accept them.
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct ProofOfWorkSettings {
+    /// When disabled, challenges are still issued but never required.
+    pub enabled: bool,
+    /// How many leading zero bits the solution's hash must have. Every extra
+    /// bit doubles the work.
+    pub difficulty: u32,
+    pub challenge_ttl_seconds: u64,
+}
+
+impl Default for ProofOfWorkSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            difficulty: 18,
+            challenge_ttl_seconds: 300,
+        }
+    }
+}
+
+/// Which addresses we take subscriptions for, beyond being well formed.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct EmailPolicySettings {
+    pub mode: DomainPolicyMode,
+    /// Refused on top of the built-in list of disposable email providers.
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    /// The only domains accepted in allowlist mode.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// Ask about near misses of common domains, such as `gmial.com`,
+    /// suggesting the likely intended address. Clients can insist on the
+    /// address as typed.
+    pub suggest_corrections: bool,
+    /// Refuse domains that have nowhere to deliver mail to.
+    pub check_mx: bool,
+}
+
+impl Default for EmailPolicySettings {
+    fn default() -> Self {
+        Self {
+            mode: DomainPolicyMode::Blocklist,
+            blocked_domains: vec![],
+            allowed_domains: vec![],
+            suggest_corrections: true,
+            check_mx: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainPolicyMode {
+    /// Accept every domain except blocked and disposable ones.
+    Blocklist,
+    /// Accept `allowed_domains` only.
+    Allowlist,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -33,12 +156,62 @@ pub struct DatabaseSettings {
 
 #[derive(Clone, serde::Deserialize)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: SecretString,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub outbox: Option<OutboxSettings>,
+    pub webhook: WebhookSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    Postmark,
+    Smtp,
+    Outbox,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    pub pool_max_size: u32,
+}
+
+/// Basic auth credentials the email provider must present when calling
+/// `/webhooks/email-provider`.
+#[derive(Clone, serde::Deserialize)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct OutboxSettings {
+    pub directory: PathBuf,
+}
+
+/// How the connection to the SMTP relay is secured.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, only meant for local relays and tests.
+    None,
+    /// Upgrade a plain connection with `STARTTLS` (usually port 587).
+    Starttls,
+    /// TLS from the first byte (usually port 465).
+    Implicit,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
 pub enum Environment {
     Local,
     Production,
@@ -69,6 +242,7 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .prefix_separator("_")
                 .separator("__"),
         )
+        .set_override("environment", environment.as_str())?
         .build()?;
 
     settings.try_deserialize::<Settings>()
@@ -122,6 +296,33 @@ impl TryFrom<String> for Environment {
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> Arc<dyn EmailSender> {
+        let sender = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        match self.provider {
+            EmailProvider::Postmark => Arc::new(PostmarkClient::new(
+                self.base_url,
+                sender,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Smtp => Arc::new(
+                SmtpClient::new(
+                    self.smtp.expect("Missing `email_client.smtp` settings."),
+                    sender,
+                    timeout,
+                )
+                .expect("Failed to build the SMTP transport."),
+            ),
+            EmailProvider::Outbox => Arc::new(OutboxClient::new(
+                self.outbox
+                    .expect("Missing `email_client.outbox` settings.")
+                    .directory,
+                sender,
+            )),
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
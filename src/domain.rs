@@ -1,9 +1,13 @@
 //! src/domain.rs
 
+mod email_format;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_token;
 
+pub use email_format::EmailFormat;
 pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
+pub use subscriber_email::{SubscriberEmail, normalize_email};
 pub use subscriber_name::SubscriberName;
+pub use subscription_token::SubscriptionToken;
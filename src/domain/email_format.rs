@@ -0,0 +1,30 @@
+/// How a subscriber wants to receive newsletter issues.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailFormat {
+    /// HTML, with the plain-text version as a fallback.
+    Html,
+    /// Plain text only.
+    Text,
+}
+
+impl EmailFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailFormat::Html => "html",
+            EmailFormat::Text => "text",
+        }
+    }
+}
+
+impl TryFrom<String> for EmailFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "html" => Ok(Self::Html),
+            "text" => Ok(Self::Text),
+            other => Err(format!("{} is not a supported email format.", other)),
+        }
+    }
+}
@@ -1,32 +1,78 @@
 use validator::ValidateEmail;
 
+/// An email address, as typed by its owner and in the normalised form we
+/// compare addresses by.
 #[derive(Debug, serde::Deserialize)]
-pub struct SubscriberEmail(String);
+#[serde(try_from = "String")]
+pub struct SubscriberEmail {
+    display: String,
+    normalized: String,
+}
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.display
     }
 }
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.display.fmt(f)
+    }
+}
+
+impl TryFrom<String> for SubscriberEmail {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::parse(s)
     }
 }
 
 impl SubscriberEmail {
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if s.validate_email() {
-            Ok(Self(s))
+        let display = s.trim();
+        if display.validate_email() {
+            Ok(Self {
+                normalized: normalize_email(display),
+                display: display.to_owned(),
+            })
         } else {
             Err(format!("{} is not a valid subscriber email.", s))
         }
     }
+
+    /// The form addresses are told apart by: two addresses are the same
+    /// subscriber if and only if their normalised forms are equal.
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
+    /// The normalised part after the `@`.
+    pub fn domain(&self) -> &str {
+        self.normalized.rsplit('@').next().unwrap_or_default()
+    }
+}
+
+/// Trim `email`, lowercase it and encode an internationalised domain as
+/// punycode.
+///
+/// Local parts are case-sensitive on paper, but no provider we know of
+/// treats them so, and people do not type them consistently.
+pub fn normalize_email(email: &str) -> String {
+    let email = email.trim();
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) => {
+            let domain = idna::domain_to_ascii(domain)
+                .unwrap_or_else(|_| domain.to_lowercase());
+            format!("{}@{}", local_part.to_lowercase(), domain)
+        }
+        None => email.to_lowercase(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberEmail;
+    use super::{SubscriberEmail, normalize_email};
     use claims::{assert_err, assert_ok};
     use fake::{
         Fake,
@@ -49,6 +95,39 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn the_domain_follows_the_at_symbol() {
+        let email = SubscriberEmail::parse("dog@cat.com".to_string()).unwrap();
+        assert_eq!(email.domain(), "cat.com");
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email =
+            SubscriberEmail::parse("  ursula@gmail.com\n".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@gmail.com");
+    }
+
+    #[test]
+    fn the_display_form_is_kept_as_typed() {
+        let email =
+            SubscriberEmail::parse("Ursula@Gmail.com".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@Gmail.com");
+        assert_eq!(email.normalized(), "ursula@gmail.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_normalised_to_punycode() {
+        assert_eq!(
+            normalize_email("Ursula@Bücher.example"),
+            "ursula@xn--bcher-kva.example"
+        );
+        let email = SubscriberEmail::parse("ursula@bücher.example".to_string())
+            .unwrap();
+        assert_eq!(email.as_ref(), "ursula@bücher.example");
+        assert_eq!(email.domain(), "xn--bcher-kva.example");
+    }
+
     #[test]
     fn email_is_valid() {
         let email = "dog@cat.com".to_string();
@@ -0,0 +1,84 @@
+use rand::{Rng, distributions::Alphanumeric};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Length of a freshly generated token: 25 alphanumeric characters carry
+/// about 148 bits of entropy.
+const TOKEN_LENGTH: usize = 25;
+
+/// A confirmation token, as sent to the subscriber.
+///
+/// Only its SHA-256 digest is ever stored, so a copy of the database is not
+/// enough to confirm a subscription.
+#[derive(Debug)]
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    /// Draw a new token from the thread-local CSPRNG.
+    pub fn generate() -> Self {
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(TOKEN_LENGTH)
+            .collect();
+        Self(token)
+    }
+
+    /// Wrap a token received from a confirmation link. Any string is
+    /// accepted: a malformed token simply matches no stored digest.
+    pub fn from_link(token: String) -> Self {
+        Self(token)
+    }
+
+    /// The hex-encoded digest we store and look tokens up by.
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.0.as_bytes()))
+    }
+
+    /// Compare against a stored digest in constant time.
+    pub fn matches(&self, stored_hash: &str) -> bool {
+        self.hash().as_bytes().ct_eq(stored_hash.as_bytes()).into()
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionToken;
+
+    #[test]
+    fn generated_tokens_are_long_and_alphanumeric() {
+        let token = SubscriptionToken::generate();
+        assert!(token.as_ref().len() >= 25);
+        assert!(token.as_ref().chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn generated_tokens_are_unique() {
+        let a = SubscriptionToken::generate();
+        let b = SubscriptionToken::generate();
+        assert_ne!(a.as_ref(), b.as_ref());
+    }
+
+    #[test]
+    fn the_hash_does_not_contain_the_token() {
+        let token = SubscriptionToken::generate();
+        let hash = token.hash();
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains(token.as_ref()));
+    }
+
+    #[test]
+    fn a_token_matches_its_own_hash_only() {
+        let token = SubscriptionToken::generate();
+        let other = SubscriptionToken::generate();
+        assert!(token.matches(&token.hash()));
+        assert!(!token.matches(&other.hash()));
+        assert!(!token.matches(token.as_ref()));
+    }
+}
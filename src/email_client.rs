@@ -1,211 +1,168 @@
-use reqwest::Client;
-use secrecy::{ExposeSecret, SecretString};
-
-use crate::domain::SubscriberEmail;
-
-#[allow(dead_code)]
-pub struct EmailClient {
-    http_client: Client,
-    base_url: String,
-    sender: SubscriberEmail,
-    authorization_token: SecretString,
-}
-
-impl EmailClient {
-    pub fn new(
-        base_url: String,
-        sender: SubscriberEmail,
-        authorization_token: SecretString,
-        timeout: std::time::Duration,
-    ) -> Self {
-        let http_client = reqwest::ClientBuilder::new()
-            .timeout(timeout)
-            .build()
-            .unwrap();
-
-        EmailClient {
-            http_client,
-            base_url,
-            sender,
-            authorization_token,
-        }
-    }
-
-    #[allow(unused_variables)]
-    pub async fn send_email(
+//! src/email_client.rs
+
+mod outbox;
+mod postmark;
+mod smtp;
+
+pub use outbox::{OutboxClient, OutboxEntry, read_outbox_index};
+pub use postmark::PostmarkClient;
+pub use smtp::SmtpClient;
+
+use crate::{domain::SubscriberEmail, routes::error_chain_fmt};
+
+/// Anything that can deliver an email on our behalf.
+///
+/// Routes and the delivery worker only depend on this trait; which backend
+/// is used is decided by `email_client.provider` in the configuration.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Send an email to `recipient`. An empty `html_body` sends a plain-text
+    /// only email.
+    ///
+    /// When `unsubscribe_url` is set the message carries the RFC 8058
+    /// `List-Unsubscribe` and `List-Unsubscribe-Post` headers, so that mail
+    /// clients can offer a one-click unsubscribe button. Without a `sender`
+    /// the email comes from the configured sender address.
+    async fn send_email(
         &self,
-        receiver: &SubscriberEmail,
+        recipient: &SubscriberEmail,
         subject: &str,
         html_body: &str,
         text_body: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
-
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: receiver.as_ref(),
-            subject,
-            text_body,
-            html_body,
-        };
-
-        let response = self
-            .http_client
-            .post(&url)
-            .json(&request_body)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .header("Accept", "application/json")
-            .send()
-            .await?
-            .error_for_status()?;
-        println!("response = {:?}", response);
-        // let response = r#"{
-        //     "ErrorCode":0,
-        //     "Message":"OK",
-        //     "MessageID":"ababababababababababab",
-        //     "SubmittedAt":"2025-03-27T23:15:33.175091Z",
-        //     "To":"an_email@domain.com"
-        //     }"#;
-        Ok(())
+        unsubscribe_url: Option<&str>,
+        sender: Option<&SenderIdentity>,
+    ) -> Result<SentEmail, EmailError>;
+
+    /// Send several emails, in as few round trips as the backend allows.
+    ///
+    /// Returns one result per email, in the same order, so that a rejected
+    /// recipient does not fail the rest of the batch. Backends without a
+    /// batch API send the emails one by one.
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Vec<Result<SentEmail, EmailError>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(
+                self.send_email(
+                    email.recipient,
+                    email.subject,
+                    email.html_body,
+                    email.text_body,
+                    email.unsubscribe_url,
+                    email.sender,
+                )
+                .await,
+            );
+        }
+        results
     }
 }
 
-#[derive(Debug, serde::Serialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    text_body: &'a str,
-    html_body: &'a str,
+/// One of the emails handed to `EmailSender::send_batch`.
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub unsubscribe_url: Option<&'a str>,
+    pub sender: Option<&'a SenderIdentity>,
 }
 
-#[cfg(test)]
-mod test {
-    use claims::{assert_err, assert_ok};
-    use fake::faker::internet::en::SafeEmail;
-    use fake::faker::lorem::en::{Paragraph, Sentence};
-    use fake::{Fake, Faker};
-    use wiremock::matchers::{any, header, header_exists, method, path};
-    use wiremock::{Mock, MockServer, ResponseTemplate};
-
-    use super::*;
-    use crate::domain::SubscriberEmail;
-
-    struct SendEmailBodyMatcher;
-
-    impl wiremock::Match for SendEmailBodyMatcher {
-        fn matches(&self, request: &wiremock::Request) -> bool {
-            match serde_json::from_slice::<serde_json::Value>(&request.body) {
-                Ok(body) => {
-                    body.get("From").is_some()
-                        && body.get("To").is_some()
-                        && body.get("Subject").is_some()
-                        && body.get("HtmlBody").is_some()
-                }
-                Err(e) => {
-                    println!("Error in body: {:?}", e);
-                    false
-                }
-            }
-        }
-    }
+/// Who an email comes from, when it is not the configured sender.
+#[derive(Debug, Default)]
+pub struct SenderIdentity {
+    pub name: Option<String>,
+    /// Has to be an address the email provider lets us send from.
+    pub email: Option<SubscriberEmail>,
+}
 
-    fn subject() -> String {
-        Sentence(1..2).fake()
+/// The `From` mailbox of an email sent as `sender`, falling back on
+/// `default` for the address.
+fn from_mailbox(
+    sender: Option<&SenderIdentity>,
+    default: &SubscriberEmail,
+) -> String {
+    let email = sender.and_then(|s| s.email.as_ref()).unwrap_or(default);
+    match sender.and_then(|s| s.name.as_deref()) {
+        Some(name) => format!(
+            "\"{}\" <{}>",
+            name.replace('\\', "\\\\").replace('"', "\\\""),
+            email.as_ref()
+        ),
+        None => email.as_ref().to_owned(),
     }
+}
 
-    fn content() -> String {
-        Paragraph(1..10).fake()
-    }
+/// What the backend told us about an email it accepted.
+#[derive(Debug, Default)]
+pub struct SentEmail {
+    /// The identifier the provider assigned to the message, used to match
+    /// later delivery events (bounces, complaints) to the delivery.
+    pub message_id: Option<String>,
+}
 
-    fn email() -> SubscriberEmail {
-        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
-    }
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    #[error("Failed to send the email, but retrying may succeed.")]
+    Transient(#[source] anyhow::Error),
+    #[error("The email was rejected.")]
+    Permanent(#[source] anyhow::Error),
+    #[error("The email provider is rate limiting us.")]
+    RateLimited(#[source] anyhow::Error),
+    #[error("The email provider rejected our credentials.")]
+    Unauthorized(#[source] anyhow::Error),
+    #[error("The recipient address is invalid.")]
+    InvalidRecipient(#[source] anyhow::Error),
+    #[error("The recipient is inactive (it bounced or complained before).")]
+    InactiveRecipient(#[source] anyhow::Error),
+}
 
-    fn email_client(uri: String) -> EmailClient {
-        EmailClient::new(
-            uri,
-            email(),
-            SecretString::new(Faker.fake::<String>().into()),
-            std::time::Duration::from_millis(200),
+impl EmailError {
+    /// Whether sending the same email again later may succeed.
+    ///
+    /// Credential failures count as transient: they are fixed by updating
+    /// the configuration, and every delivery would otherwise be dead-lettered
+    /// in the meantime.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            EmailError::Transient(_)
+                | EmailError::RateLimited(_)
+                | EmailError::Unauthorized(_)
         )
     }
 
-    #[tokio::test]
-    pub async fn send_email_sends_the_expected_request() {
-        let mock_server = MockServer::start().await;
-
-        Mock::given(path("/email"))
-            .and(method("POST"))
-            .and(header("Accept", "application/json"))
-            .and(header("Content-Type", "application/json"))
-            .and(header_exists("X-Postmark-Server-Token"))
-            .and(SendEmailBodyMatcher)
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let _ = email_client(mock_server.uri())
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-    }
-
-    #[tokio::test]
-    pub async fn send_email_succeeds_if_the_server_returns_200() {
-        let mock_server = MockServer::start().await;
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let outcome = email_client(mock_server.uri())
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        assert_ok!(outcome)
-    }
-
-    #[tokio::test]
-    pub async fn send_email_fails_if_the_server_returns_500() {
-        let mock_server = MockServer::start().await;
-
-        Mock::given(any())
-            .respond_with(ResponseTemplate::new(500))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let outcome = email_client(mock_server.uri())
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        assert_err!(outcome);
+    /// A copy of this error, for when one failure affects several emails.
+    ///
+    /// The source chain is flattened into a single message.
+    fn duplicate(&self) -> Self {
+        match self {
+            EmailError::Transient(e) => {
+                EmailError::Transient(anyhow::anyhow!("{:#}", e))
+            }
+            EmailError::Permanent(e) => {
+                EmailError::Permanent(anyhow::anyhow!("{:#}", e))
+            }
+            EmailError::RateLimited(e) => {
+                EmailError::RateLimited(anyhow::anyhow!("{:#}", e))
+            }
+            EmailError::Unauthorized(e) => {
+                EmailError::Unauthorized(anyhow::anyhow!("{:#}", e))
+            }
+            EmailError::InvalidRecipient(e) => {
+                EmailError::InvalidRecipient(anyhow::anyhow!("{:#}", e))
+            }
+            EmailError::InactiveRecipient(e) => {
+                EmailError::InactiveRecipient(anyhow::anyhow!("{:#}", e))
+            }
+        }
     }
+}
 
-    #[tokio::test]
-    pub async fn send_email_times_out_if_the_server_takes_too_long() {
-        let mock_server = MockServer::start().await;
-
-        Mock::given(any())
-            .respond_with(
-                ResponseTemplate::new(200)
-                    .set_delay(std::time::Duration::from_secs(11)),
-            )
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let outcome = email_client(mock_server.uri())
-            .send_email(&email(), &subject(), &content(), &content())
-            .await;
-
-        assert_err!(outcome);
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
     }
 }
@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use super::smtp::{build_message, message_id};
+use super::{EmailError, EmailSender, SenderIdentity, SentEmail, from_mailbox};
+use crate::domain::SubscriberEmail;
+
+const INDEX_FILE: &str = "index.jsonl";
+
+/// Captures outgoing email on disk instead of delivering it.
+///
+/// Every message is written as an `.eml` file into `directory`, and listed
+/// in an `index.jsonl` file next to it. Meant for local development only.
+///
+/// The index is append-only, one JSON line per message, so that the API and
+/// the delivery worker can each have a client on the same directory.
+pub struct OutboxClient {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub captured_at: DateTime<Utc>,
+    pub file: String,
+}
+
+impl OutboxClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        OutboxClient { directory, sender }
+    }
+
+    async fn store(
+        &self,
+        entry: OutboxEntry,
+        message: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create the outbox directory.")?;
+        tokio::fs::write(self.directory.join(&entry.file), message)
+            .await
+            .context("Failed to write the message to the outbox.")?;
+
+        // A single write to a file opened for appending: concurrent writers
+        // cannot interleave or overwrite each other's lines.
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut index = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(INDEX_FILE))
+            .await
+            .context("Failed to open the outbox index.")?;
+        index
+            .write_all(&line)
+            .await
+            .context("Failed to update the outbox index.")?;
+        index
+            .flush()
+            .await
+            .context("Failed to update the outbox index.")?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for OutboxClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+        unsubscribe_url: Option<&str>,
+        sender: Option<&SenderIdentity>,
+    ) -> Result<SentEmail, EmailError> {
+        let from = from_mailbox(sender, &self.sender);
+        let message = build_message(
+            &from,
+            recipient,
+            subject,
+            html_body,
+            text_body,
+            unsubscribe_url,
+        )?;
+
+        let message_id = message_id(&message);
+        let id = Uuid::new_v4();
+        let entry = OutboxEntry {
+            id,
+            from,
+            to: recipient.as_ref().to_owned(),
+            subject: subject.to_owned(),
+            captured_at: Utc::now(),
+            file: format!("{}.eml", id),
+        };
+        let path = self.directory.join(&entry.file);
+
+        self.store(entry, &message.formatted())
+            .await
+            .map_err(EmailError::Transient)?;
+
+        tracing::info!(
+            "Captured email to {} in {}",
+            recipient.as_ref(),
+            path.display()
+        );
+        Ok(SentEmail { message_id })
+    }
+}
+
+/// Read the entries captured in `directory`, oldest first.
+pub async fn read_outbox_index(
+    directory: &Path,
+) -> Result<Vec<OutboxEntry>, anyhow::Error> {
+    match tokio::fs::read_to_string(directory.join(INDEX_FILE)).await {
+        Ok(index) => index
+            .lines()
+            .map(|line| {
+                serde_json::from_str(line)
+                    .context("Failed to parse the outbox index.")
+            })
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).context("Failed to read the outbox index."),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use claims::assert_ok;
+    use fake::Fake;
+    use fake::faker::internet::en::SafeEmail;
+
+    use super::*;
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn outbox_directory() -> PathBuf {
+        std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_and_indexes_it() {
+        let directory = outbox_directory();
+        let client = OutboxClient::new(directory.clone(), email());
+        let recipient = email();
+
+        let outcome = client
+            .send_email(
+                &recipient,
+                "Welcome!",
+                "<p>HTML body</p>",
+                "Plain text body",
+                None,
+                None,
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let index = read_outbox_index(&directory).await.unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index[0].to, recipient.as_ref());
+        assert_eq!(index[0].subject, "Welcome!");
+
+        let message =
+            std::fs::read_to_string(directory.join(&index[0].file)).unwrap();
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("Plain text body"));
+        assert!(message.contains("<p>HTML body</p>"));
+    }
+
+    #[tokio::test]
+    async fn every_message_is_added_to_the_index() {
+        let directory = outbox_directory();
+        let client = OutboxClient::new(directory.clone(), email());
+
+        for subject in ["First", "Second"] {
+            assert_ok!(
+                client
+                    .send_email(&email(), subject, "html", "text", None, None)
+                    .await
+            );
+        }
+
+        let index = read_outbox_index(&directory).await.unwrap();
+        let subjects: Vec<_> = index.iter().map(|e| &e.subject).collect();
+        assert_eq!(subjects, ["First", "Second"]);
+    }
+
+    #[tokio::test]
+    async fn clients_sharing_a_directory_do_not_lose_entries() {
+        let directory = outbox_directory();
+        let api =
+            std::sync::Arc::new(OutboxClient::new(directory.clone(), email()));
+        let worker =
+            std::sync::Arc::new(OutboxClient::new(directory.clone(), email()));
+
+        let mut sends = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let client = if i % 2 == 0 { &api } else { &worker }.clone();
+            sends.spawn(async move {
+                client
+                    .send_email(&email(), "Subject", "html", "text", None, None)
+                    .await
+            });
+        }
+        while let Some(outcome) = sends.join_next().await {
+            assert_ok!(outcome.unwrap());
+        }
+
+        let index = read_outbox_index(&directory).await.unwrap();
+        assert_eq!(index.len(), 20);
+    }
+
+    #[tokio::test]
+    async fn an_empty_outbox_has_an_empty_index() {
+        let index = read_outbox_index(&outbox_directory()).await.unwrap();
+
+        assert!(index.is_empty());
+    }
+}
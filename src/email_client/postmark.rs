@@ -0,0 +1,771 @@
+use chrono::{DateTime, Utc};
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+
+use super::{
+    EmailError, EmailSender, OutgoingEmail, SenderIdentity, SentEmail,
+    from_mailbox,
+};
+use crate::domain::SubscriberEmail;
+
+/// Delivers email through Postmark's `/email` and `/email/batch` HTTP APIs.
+pub struct PostmarkClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: SecretString,
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: SecretString,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = reqwest::ClientBuilder::new()
+            .timeout(timeout)
+            .build()
+            .unwrap();
+
+        PostmarkClient {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkClient {
+    async fn send_email(
+        &self,
+        receiver: &SubscriberEmail,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+        unsubscribe_url: Option<&str>,
+        sender: Option<&SenderIdentity>,
+    ) -> Result<SentEmail, EmailError> {
+        let list_unsubscribe = unsubscribe_url.map(|url| format!("<{}>", url));
+        let from = from_mailbox(sender, &self.sender);
+        let request_body = SendEmailRequest {
+            from: &from,
+            to: receiver.as_ref(),
+            subject,
+            text_body,
+            html_body,
+            headers: email_headers(list_unsubscribe.as_deref()),
+        };
+
+        let (status, body) = self
+            .post("email", &request_body)
+            .await
+            .map_err(classify_transport_error)?;
+        let parsed = serde_json::from_slice::<SendEmailResponse>(&body);
+
+        if !status.is_success() {
+            return Err(classify_failure(status, parsed.ok().as_ref()));
+        }
+        match parsed {
+            Ok(response) => {
+                tracing::debug!(
+                    message_id = ?response.message_id,
+                    submitted_at = ?response.submitted_at,
+                    "Postmark accepted the email."
+                );
+                Ok(SentEmail {
+                    message_id: response.message_id,
+                })
+            }
+            Err(e) => {
+                // The email went out, we just can't tell which id it got.
+                tracing::warn!(
+                    error.message = %e,
+                    "Failed to parse a successful Postmark response."
+                );
+                Ok(SentEmail::default())
+            }
+        }
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Vec<Result<SentEmail, EmailError>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            results.extend(self.send_chunk(chunk).await);
+        }
+        results
+    }
+}
+
+/// Postmark accepts at most 500 messages per `/email/batch` call.
+const MAX_BATCH_SIZE: usize = 500;
+
+impl PostmarkClient {
+    async fn post(
+        &self,
+        path: &str,
+        body: &impl serde::Serialize,
+    ) -> Result<(StatusCode, Vec<u8>), reqwest::Error> {
+        let response = self
+            .http_client
+            .post(format!("{}/{}", self.base_url, path))
+            .json(body)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .header("Accept", "application/json")
+            .send()
+            .await?;
+        let status = response.status();
+        Ok((status, response.bytes().await?.to_vec()))
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails with a single `/email/batch` call.
+    async fn send_chunk(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Vec<Result<SentEmail, EmailError>> {
+        let list_unsubscribes: Vec<_> = emails
+            .iter()
+            .map(|e| e.unsubscribe_url.map(|url| format!("<{}>", url)))
+            .collect();
+        let froms: Vec<_> = emails
+            .iter()
+            .map(|e| from_mailbox(e.sender, &self.sender))
+            .collect();
+        let request_body: Vec<_> = emails
+            .iter()
+            .zip(&list_unsubscribes)
+            .zip(&froms)
+            .map(|((email, list_unsubscribe), from)| SendEmailRequest {
+                from,
+                to: email.recipient.as_ref(),
+                subject: email.subject,
+                text_body: email.text_body,
+                html_body: email.html_body,
+                headers: email_headers(list_unsubscribe.as_deref()),
+            })
+            .collect();
+
+        let (status, body) = match self.post("email/batch", &request_body).await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                return for_each_email(emails, classify_transport_error(e));
+            }
+        };
+        let parsed = serde_json::from_slice::<Vec<SendEmailResponse>>(&body);
+
+        if !status.is_success() {
+            // The whole batch was refused, e.g. because of a bad token.
+            let error = serde_json::from_slice::<SendEmailResponse>(&body).ok();
+            return for_each_email(
+                emails,
+                classify_failure(status, error.as_ref()),
+            );
+        }
+        match parsed {
+            Ok(responses) if responses.len() == emails.len() => responses
+                .into_iter()
+                .map(|response| {
+                    if response.error_code == 0 {
+                        Ok(SentEmail {
+                            message_id: response.message_id,
+                        })
+                    } else {
+                        Err(classify_failure(
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            Some(&response),
+                        ))
+                    }
+                })
+                .collect(),
+            _ => {
+                // The emails went out, we just can't tell which ids they got.
+                tracing::warn!(
+                    "Failed to parse a successful Postmark batch response."
+                );
+                emails.iter().map(|_| Ok(SentEmail::default())).collect()
+            }
+        }
+    }
+}
+
+fn email_headers(list_unsubscribe: Option<&str>) -> Vec<EmailHeader<'_>> {
+    match list_unsubscribe {
+        Some(list_unsubscribe) => vec![
+            EmailHeader {
+                name: "List-Unsubscribe",
+                value: list_unsubscribe,
+            },
+            EmailHeader {
+                name: "List-Unsubscribe-Post",
+                value: "List-Unsubscribe=One-Click",
+            },
+        ],
+        None => vec![],
+    }
+}
+
+/// Report the same failure for every email of a batch.
+fn for_each_email(
+    emails: &[OutgoingEmail<'_>],
+    error: EmailError,
+) -> Vec<Result<SentEmail, EmailError>> {
+    emails.iter().map(|_| Err(error.duplicate())).collect()
+}
+
+/// Postmark's `ErrorCode` for a missing or invalid server token.
+const INVALID_API_TOKEN: i64 = 10;
+/// Postmark's `ErrorCode` for a malformed request, including invalid
+/// recipient addresses.
+const INVALID_EMAIL_REQUEST: i64 = 300;
+/// Postmark's `ErrorCode` for a recipient that previously bounced, complained
+/// or unsubscribed.
+const INACTIVE_RECIPIENT: i64 = 406;
+
+/// Map a non-2xx answer to the matching `EmailError`.
+///
+/// Server errors and rate limiting are worth retrying; any other client
+/// error will fail again in exactly the same way.
+fn classify_failure(
+    status: StatusCode,
+    response: Option<&SendEmailResponse>,
+) -> EmailError {
+    let error_code = response.map(|r| r.error_code);
+    let error = match response {
+        Some(r) => anyhow::anyhow!(
+            "Postmark answered {} with ErrorCode {}: {}",
+            status,
+            r.error_code,
+            r.message
+        ),
+        None => anyhow::anyhow!("Postmark answered {}", status),
+    };
+    let mentions_recipient =
+        response.is_some_and(|r| r.message.contains("'To'"));
+
+    match error_code {
+        _ if status == StatusCode::UNAUTHORIZED => {
+            EmailError::Unauthorized(error)
+        }
+        Some(INVALID_API_TOKEN) => EmailError::Unauthorized(error),
+        _ if status == StatusCode::TOO_MANY_REQUESTS => {
+            EmailError::RateLimited(error)
+        }
+        Some(INACTIVE_RECIPIENT) => EmailError::InactiveRecipient(error),
+        Some(INVALID_EMAIL_REQUEST) if mentions_recipient => {
+            EmailError::InvalidRecipient(error)
+        }
+        _ if status.is_server_error() => EmailError::Transient(error),
+        _ => EmailError::Permanent(error),
+    }
+}
+
+/// We never got an answer: timeouts and connection failures are worth
+/// retrying, a request we could not even build is not.
+fn classify_transport_error(e: reqwest::Error) -> EmailError {
+    if e.is_builder() {
+        EmailError::Permanent(e.into())
+    } else {
+        EmailError::Transient(e.into())
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    html_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<EmailHeader<'a>>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SendEmailResponse {
+    pub error_code: i64,
+    pub message: String,
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod test {
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::EmailSender;
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            match serde_json::from_slice::<serde_json::Value>(&request.body) {
+                Ok(body) => {
+                    body.get("From").is_some()
+                        && body.get("To").is_some()
+                        && body.get("Subject").is_some()
+                        && body.get("HtmlBody").is_some()
+                }
+                Err(e) => {
+                    println!("Error in body: {:?}", e);
+                    false
+                }
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(uri: String) -> PostmarkClient {
+        PostmarkClient::new(
+            uri,
+            email(),
+            SecretString::new(Faker.fake::<String>().into()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    pub async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(header("Accept", "application/json"))
+            .and(header("Content-Type", "application/json"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+    }
+
+    struct ListUnsubscribeHeadersMatcher;
+
+    impl wiremock::Match for ListUnsubscribeHeadersMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let Ok(body) =
+                serde_json::from_slice::<serde_json::Value>(&request.body)
+            else {
+                return false;
+            };
+            body["Headers"]
+                == serde_json::json!([
+                    {
+                        "Name": "List-Unsubscribe",
+                        "Value": "<https://example.com/unsubscribe>"
+                    },
+                    {
+                        "Name": "List-Unsubscribe-Post",
+                        "Value": "List-Unsubscribe=One-Click"
+                    },
+                ])
+        }
+    }
+
+    #[tokio::test]
+    pub async fn send_email_adds_list_unsubscribe_headers() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(ListUnsubscribeHeadersMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                Some("https://example.com/unsubscribe"),
+                None,
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    pub async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    pub async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    pub async fn a_500_is_a_transient_failure() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert!(assert_err!(outcome).is_transient());
+    }
+
+    #[tokio::test]
+    pub async fn a_422_is_a_permanent_failure() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert!(!assert_err!(outcome).is_transient());
+    }
+
+    async fn send_email_answered_with(
+        template: ResponseTemplate,
+    ) -> Result<SentEmail, EmailError> {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(template)
+            .mount(&mock_server)
+            .await;
+
+        email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await
+    }
+
+    fn postmark_error(error_code: i64, message: &str) -> serde_json::Value {
+        serde_json::json!({ "ErrorCode": error_code, "Message": message })
+    }
+
+    #[tokio::test]
+    pub async fn send_email_returns_the_postmark_message_id() {
+        let outcome = send_email_answered_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "ErrorCode": 0,
+                "Message": "OK",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "SubmittedAt": "2025-03-27T23:15:33.175091Z",
+                "To": "an_email@domain.com"
+            })),
+        )
+        .await;
+
+        assert_eq!(
+            assert_ok!(outcome).message_id.as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    pub async fn a_429_is_reported_as_rate_limiting() {
+        let outcome =
+            send_email_answered_with(ResponseTemplate::new(429)).await;
+
+        let e = assert_err!(outcome);
+        assert!(matches!(e, EmailError::RateLimited(_)));
+        assert!(e.is_transient());
+    }
+
+    #[tokio::test]
+    pub async fn an_invalid_token_is_reported_as_an_auth_failure() {
+        let outcome =
+            send_email_answered_with(ResponseTemplate::new(401).set_body_json(
+                postmark_error(10, "Bad or missing Server API token."),
+            ))
+            .await;
+
+        assert!(matches!(assert_err!(outcome), EmailError::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    pub async fn an_invalid_to_address_is_reported_as_an_invalid_recipient() {
+        let outcome =
+            send_email_answered_with(ResponseTemplate::new(422).set_body_json(
+                postmark_error(300, "Invalid 'To' address: 'not-an-email'."),
+            ))
+            .await;
+
+        let e = assert_err!(outcome);
+        assert!(matches!(e, EmailError::InvalidRecipient(_)));
+        assert!(!e.is_transient());
+    }
+
+    #[tokio::test]
+    pub async fn an_inactive_recipient_is_reported_as_such() {
+        let outcome = send_email_answered_with(
+            ResponseTemplate::new(422).set_body_json(postmark_error(
+                406,
+                "You tried to send to a recipient that has been marked as \
+                inactive.",
+            )),
+        )
+        .await;
+
+        let e = assert_err!(outcome);
+        assert!(matches!(e, EmailError::InactiveRecipient(_)));
+        assert!(!e.is_transient());
+    }
+
+    #[tokio::test]
+    pub async fn the_postmark_error_message_is_kept_in_the_error_chain() {
+        let outcome = send_email_answered_with(
+            ResponseTemplate::new(422)
+                .set_body_json(postmark_error(300, "Invalid email request")),
+        )
+        .await;
+
+        let e = assert_err!(outcome);
+        assert!(format!("{:?}", e).contains("Invalid email request"));
+    }
+
+    fn outgoing_emails(
+        recipients: &[SubscriberEmail],
+    ) -> Vec<OutgoingEmail<'_>> {
+        recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: "Subject",
+                html_body: "<p>HTML</p>",
+                text_body: "Text",
+                unsubscribe_url: Some("https://example.com/unsubscribe"),
+                sender: None,
+            })
+            .collect()
+    }
+
+    fn accepted_batch(request: &wiremock::Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = request.body_json().unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|_| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+
+    #[tokio::test]
+    pub async fn send_batch_splits_large_batches_into_chunks_of_500() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .respond_with(accepted_batch)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let recipients: Vec<_> = (0..501).map(|_| email()).collect();
+        let results = email_client(mock_server.uri())
+            .send_batch(&outgoing_emails(&recipients))
+            .await;
+
+        assert_eq!(results.len(), 501);
+        assert!(results.iter().all(|r| r.is_ok()));
+        let requests = mock_server.received_requests().await.unwrap();
+        let sizes: Vec<_> = requests
+            .iter()
+            .map(|r| r.body_json::<Vec<serde_json::Value>>().unwrap().len())
+            .collect();
+        assert_eq!(sizes, [500, 1]);
+    }
+
+    #[tokio::test]
+    pub async fn send_batch_maps_results_back_to_each_email() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!([
+                    { "ErrorCode": 0, "Message": "OK", "MessageID": "first" },
+                    postmark_error(406, "Inactive recipient"),
+                ]),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let recipients = [email(), email()];
+        let mut results = email_client(mock_server.uri())
+            .send_batch(&outgoing_emails(&recipients))
+            .await
+            .into_iter();
+
+        let sent = assert_ok!(results.next().unwrap());
+        assert_eq!(sent.message_id.as_deref(), Some("first"));
+        let e = assert_err!(results.next().unwrap());
+        assert!(matches!(e, EmailError::InactiveRecipient(_)));
+    }
+
+    #[tokio::test]
+    pub async fn a_rejected_batch_fails_every_email() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let recipients = [email(), email()];
+        let results = email_client(mock_server.uri())
+            .send_batch(&outgoing_emails(&recipients))
+            .await;
+
+        assert_eq!(results.len(), 2);
+        for result in results {
+            assert!(assert_err!(result).is_transient());
+        }
+    }
+
+    #[tokio::test]
+    pub async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_secs(11)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client(mock_server.uri())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,440 @@
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::PoolConfig;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::ExposeSecret;
+
+use super::{EmailError, EmailSender, SenderIdentity, SentEmail, from_mailbox};
+use crate::configuration::{SmtpSettings, SmtpTls};
+use crate::domain::SubscriberEmail;
+
+/// Delivers email through an SMTP relay, reusing pooled connections.
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpClient {
+    pub fn new(
+        settings: SmtpSettings,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let builder = match settings.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                    &settings.host,
+                )
+            }
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(
+                    &settings.host,
+                )?
+            }
+            SmtpTls::Implicit => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?
+            }
+        };
+
+        let mut builder = builder
+            .port(settings.port)
+            .timeout(Some(timeout))
+            .pool_config(PoolConfig::new().max_size(settings.pool_max_size));
+
+        if let Some(username) = settings.username {
+            let password = settings
+                .password
+                .map(|p| p.expose_secret().to_owned())
+                .unwrap_or_default();
+            builder = builder
+                .credentials(Credentials::new(username, password))
+                .authentication(vec![Mechanism::Plain, Mechanism::Login]);
+        }
+
+        Ok(SmtpClient {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_body: &str,
+        text_body: &str,
+        unsubscribe_url: Option<&str>,
+        sender: Option<&SenderIdentity>,
+    ) -> Result<SentEmail, EmailError> {
+        let message = build_message(
+            &from_mailbox(sender, &self.sender),
+            recipient,
+            subject,
+            html_body,
+            text_body,
+            unsubscribe_url,
+        )?;
+
+        let message_id = message_id(&message);
+        self.transport.send(message).await.map_err(classify_error)?;
+        Ok(SentEmail { message_id })
+    }
+}
+
+/// Build a multipart/alternative message carrying both bodies, or a plain
+/// text one if there is no HTML body.
+pub(super) fn build_message(
+    from: &str,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
+    unsubscribe_url: Option<&str>,
+) -> Result<Message, EmailError> {
+    let from: Mailbox = from
+        .parse()
+        .map_err(|e| EmailError::Permanent(anyhow::Error::new(e)))?;
+    let to: Mailbox = recipient
+        .as_ref()
+        .parse()
+        .map_err(|e| EmailError::Permanent(anyhow::Error::new(e)))?;
+
+    let mut builder = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .message_id(None);
+    if let Some(url) = unsubscribe_url {
+        builder = builder
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{}>", url),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".into(),
+            ));
+    }
+
+    if html_body.is_empty() {
+        builder.singlepart(SinglePart::plain(text_body.to_owned()))
+    } else {
+        builder.multipart(MultiPart::alternative_plain_html(
+            text_body.to_owned(),
+            html_body.to_owned(),
+        ))
+    }
+    .map_err(|e| EmailError::Permanent(anyhow::Error::new(e)))
+}
+
+/// The `Message-ID` header generated by `build_message`.
+pub(super) fn message_id(message: &Message) -> Option<String> {
+    message.headers().get_raw("Message-ID").map(str::to_owned)
+}
+
+/// 5xx replies and client-side errors will fail again in exactly the same
+/// way; 4xx replies, timeouts and connection problems are worth retrying.
+fn classify_error(e: lettre::transport::smtp::Error) -> EmailError {
+    if e.is_permanent() || e.is_client() {
+        EmailError::Permanent(e.into())
+    } else {
+        EmailError::Transient(e.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use claims::{assert_err, assert_ok};
+    use fake::Fake;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use secrecy::SecretString;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A minimal in-process SMTP server recording what it receives.
+    ///
+    /// Every command is accepted, except `RCPT TO` which is answered with
+    /// `rcpt_reply` so that tests can simulate rejections.
+    #[derive(Clone)]
+    struct SmtpStandIn {
+        port: u16,
+        commands: Arc<Mutex<Vec<String>>>,
+        messages: Arc<Mutex<Vec<String>>>,
+        connections: Arc<Mutex<usize>>,
+    }
+
+    impl SmtpStandIn {
+        async fn start(rcpt_reply: &'static str) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let stand_in = SmtpStandIn {
+                port: listener.local_addr().unwrap().port(),
+                commands: Default::default(),
+                messages: Default::default(),
+                connections: Default::default(),
+            };
+
+            let server = stand_in.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    *server.connections.lock().unwrap() += 1;
+                    tokio::spawn(server.clone().serve(stream, rcpt_reply));
+                }
+            });
+            stand_in
+        }
+
+        async fn serve(
+            self,
+            stream: tokio::net::TcpStream,
+            rcpt_reply: &'static str,
+        ) {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                self.commands.lock().unwrap().push(line.clone());
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") {
+                    "250-localhost\r\n250 AUTH PLAIN LOGIN\r\n"
+                } else if command.starts_with("AUTH") {
+                    "235 2.7.0 Authentication successful\r\n"
+                } else if command.starts_with("RCPT") {
+                    rcpt_reply
+                } else if command.starts_with("DATA") {
+                    writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                    let mut message = String::new();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if line == "." {
+                            break;
+                        }
+                        message.push_str(&line);
+                        message.push('\n');
+                    }
+                    self.messages.lock().unwrap().push(message);
+                    "250 OK\r\n"
+                } else if command.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    "250 OK\r\n"
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        }
+
+        fn settings(&self) -> SmtpSettings {
+            SmtpSettings {
+                host: "127.0.0.1".into(),
+                port: self.port,
+                tls: SmtpTls::None,
+                username: None,
+                password: None,
+                pool_max_size: 2,
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(settings: SmtpSettings) -> SmtpClient {
+        SmtpClient::new(
+            settings,
+            email(),
+            std::time::Duration::from_millis(500),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_a_multipart_alternative_message() {
+        let server = SmtpStandIn::start("250 OK\r\n").await;
+        let recipient = email();
+
+        let outcome = email_client(server.settings())
+            .send_email(
+                &recipient,
+                "Our newsletter",
+                "<p>HTML body</p>",
+                "Plain text body",
+                Some("https://example.com/unsubscribe"),
+                None,
+            )
+            .await;
+
+        let sent = assert_ok!(outcome);
+        let messages = server.messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        let message_id = sent.message_id.unwrap();
+        assert!(message.contains(&format!("Message-ID: {}", message_id)));
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("Content-Type: text/plain"));
+        assert!(message.contains("Content-Type: text/html"));
+        assert!(message.contains("Plain text body"));
+        assert!(message.contains("<p>HTML body</p>"));
+        assert!(message.contains("Subject: Our newsletter"));
+        assert!(message.contains(&format!("To: {}", recipient.as_ref())));
+        assert!(
+            message.contains(
+                "List-Unsubscribe: <https://example.com/unsubscribe>"
+            )
+        );
+        assert!(
+            message
+                .contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_without_html_sends_plain_text_only() {
+        let server = SmtpStandIn::start("250 OK\r\n").await;
+
+        let outcome = email_client(server.settings())
+            .send_email(&email(), &subject(), "", "Plain text body", None, None)
+            .await;
+
+        assert_ok!(outcome);
+        let messages = server.messages.lock().unwrap();
+        let message = &messages[0];
+        assert!(!message.contains("multipart/alternative"));
+        assert!(!message.contains("text/html"));
+        assert!(message.contains("Content-Type: text/plain"));
+        assert!(message.contains("Plain text body"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_when_credentials_are_configured() {
+        let server = SmtpStandIn::start("250 OK\r\n").await;
+        let settings = SmtpSettings {
+            username: Some("relay-user".into()),
+            password: Some(SecretString::from("relay-password")),
+            ..server.settings()
+        };
+
+        let outcome = email_client(settings)
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let commands = server.commands.lock().unwrap();
+        assert!(commands.iter().any(|c| c.starts_with("AUTH PLAIN")));
+    }
+
+    #[tokio::test]
+    async fn connections_are_reused_across_sends() {
+        let server = SmtpStandIn::start("250 OK\r\n").await;
+        let client = email_client(server.settings());
+
+        for _ in 0..3 {
+            assert_ok!(
+                client
+                    .send_email(
+                        &email(),
+                        &subject(),
+                        &content(),
+                        &content(),
+                        None,
+                        None
+                    )
+                    .await
+            );
+            // Connections are handed back to the pool by a spawned task.
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        assert_eq!(server.messages.lock().unwrap().len(), 3);
+        assert_eq!(*server.connections.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_4xx_reply_is_a_transient_failure() {
+        let server = SmtpStandIn::start("451 4.3.0 Try again later\r\n").await;
+
+        let outcome = email_client(server.settings())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert!(assert_err!(outcome).is_transient());
+    }
+
+    #[tokio::test]
+    async fn a_5xx_reply_is_a_permanent_failure() {
+        let server = SmtpStandIn::start("550 5.1.1 No such user\r\n").await;
+
+        let outcome = email_client(server.settings())
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert!(!assert_err!(outcome).is_transient());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_relay_is_a_transient_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let settings = SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            pool_max_size: 1,
+        };
+
+        let outcome = email_client(settings)
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                None,
+                None,
+            )
+            .await;
+
+        assert!(assert_err!(outcome).is_transient());
+    }
+}
@@ -0,0 +1,122 @@
+//! Bring stored `email_normalized` values in line with [`normalize_email`].
+//!
+//! The migration that introduced the column could only lowercase addresses,
+//! while the application also punycode-encodes internationalised domains.
+//! Subscribers on such domains would not be found by their own address.
+//!
+//! Run once after migrating, with the `normalize_emails` binary.
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::normalize_email;
+
+struct StoredSubscription {
+    id: Uuid,
+    email: String,
+    email_normalized: String,
+}
+
+/// Re-normalize every address the migration could not, keeping a single row
+/// per normalized address: the most engaged one, then the oldest.
+///
+/// Only addresses with a non-ASCII domain can be affected. Once they are
+/// fixed, running this again takes no lock and changes nothing.
+///
+/// Returns how many addresses were re-normalized.
+#[tracing::instrument(name = "Re-normalize subscriber emails", skip(pool))]
+pub async fn normalize_stored_emails(
+    pool: &PgPool,
+) -> Result<usize, anyhow::Error> {
+    if stale_subscriptions(pool).await?.is_empty() {
+        return Ok(0);
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // New subscriptions must not claim an address while it is being moved.
+    sqlx::query!("lock table subscriptions in share row exclusive mode")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to lock the subscriptions.")?;
+    let stale = stale_subscriptions(&mut *transaction).await?;
+    let n_normalized = stale.len();
+    for (subscription, normalized) in stale {
+        // Of the rows claiming the address, all but the best one go.
+        // Confirmation tokens and list subscriptions go with them.
+        sqlx::query!(
+            r#"
+            delete from subscriptions
+            where id = any(
+                select id
+                from subscriptions
+                where id = $1 or email_normalized = $2
+                order by
+                    case status
+                        when 'confirmed' then 0
+                        when 'pending_confirmation' then 1
+                        else 2
+                    end,
+                    subscribed_at
+                offset 1
+            )
+            "#,
+            subscription.id,
+            normalized
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete a duplicate subscription.")?;
+        sqlx::query!(
+            r#"
+            update subscriptions
+            set email_normalized = $2
+            where id = $1
+            "#,
+            subscription.id,
+            normalized
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update a normalized email.")?;
+        tracing::info!(
+            subscriber_id = %subscription.id,
+            "Re-normalized a subscriber email"
+        );
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the normalized emails.")?;
+    Ok(n_normalized)
+}
+
+/// The subscriptions whose stored value differs from [`normalize_email`],
+/// along with the value it should be.
+async fn stale_subscriptions<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+) -> Result<Vec<(StoredSubscription, String)>, anyhow::Error> {
+    // Non-ASCII local parts are only lowercased, by the migration and by the
+    // application alike: they need not be looked at.
+    let candidates = sqlx::query_as!(
+        StoredSubscription,
+        r#"
+        select id, email, email_normalized
+        from subscriptions
+        where email_normalized ~ '@[^@]*[^[:ascii:]][^@]*$'
+        "#
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to fetch the subscriptions to normalize.")?;
+    Ok(candidates
+        .into_iter()
+        .filter_map(|subscription| {
+            let normalized = normalize_email(&subscription.email);
+            (normalized != subscription.email_normalized)
+                .then_some((subscription, normalized))
+        })
+        .collect())
+}
@@ -0,0 +1,324 @@
+//! Checks on the domain of a subscriber's address, so that throwaway
+//! addresses are refused and likely typos caught up front instead of
+//! bouncing later.
+mod mx;
+
+use std::sync::Arc;
+
+pub use mx::{DnsMxResolver, MxResolver};
+
+use crate::{
+    configuration::{DomainPolicyMode, EmailPolicySettings},
+    domain::SubscriberEmail,
+};
+
+/// Providers handing out throwaway addresses. `blocked_domains` extends it.
+const DISPOSABLE_DOMAINS: &[&str] = &[
+    "10minutemail.com",
+    "dispostable.com",
+    "getnada.com",
+    "guerrillamail.com",
+    "mailinator.com",
+    "maildrop.cc",
+    "sharklasers.com",
+    "temp-mail.org",
+    "throwawaymail.com",
+    "trashmail.com",
+    "yopmail.com",
+];
+
+/// Domains common enough that a near miss is most likely a typo.
+const COMMON_DOMAINS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "googlemail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "yahoo.com",
+];
+
+pub struct EmailPolicy {
+    settings: EmailPolicySettings,
+    resolver: Option<Arc<dyn MxResolver>>,
+}
+
+impl EmailPolicy {
+    pub fn new(settings: EmailPolicySettings) -> Self {
+        let resolver = settings.check_mx.then(|| {
+            Arc::new(DnsMxResolver::from_system_conf()) as Arc<dyn MxResolver>
+        });
+        Self { settings, resolver }
+    }
+
+    /// Use `resolver` for MX lookups, whether or not `check_mx` is set.
+    pub fn with_resolver(
+        settings: EmailPolicySettings,
+        resolver: Arc<dyn MxResolver>,
+    ) -> Self {
+        Self {
+            settings,
+            resolver: Some(resolver),
+        }
+    }
+
+    /// Check that we are willing and able to deliver to `email`.
+    ///
+    /// The error is meant for the subscriber: it says what to fix.
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let domain = email.domain();
+        self.check_domain(domain)?;
+
+        if let Some(resolver) = &self.resolver {
+            match resolver.accepts_mail(domain).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(format!(
+                        "{} does not accept email. Please check the address.",
+                        domain
+                    ));
+                }
+                // A flaky resolver must not stop people from subscribing.
+                Err(e) => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to look up the MX records of {}.",
+                        domain
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The address the subscriber most likely meant, if `email` is one typo
+    /// away from a common domain.
+    ///
+    /// Only a suggestion: real domains such as `ge.com` are near misses too.
+    pub fn suggest_correction(
+        &self,
+        email: &SubscriberEmail,
+    ) -> Option<String> {
+        if !self.settings.suggest_corrections
+            || self.settings.mode != DomainPolicyMode::Blocklist
+        {
+            return None;
+        }
+        let suggestion = suggest_domain(email.domain())?;
+        let (local_part, _) =
+            email.as_ref().rsplit_once('@').unwrap_or_default();
+        Some(format!("{}@{}", local_part, suggestion))
+    }
+
+    fn check_domain(&self, domain: &str) -> Result<(), String> {
+        match self.settings.mode {
+            DomainPolicyMode::Allowlist => {
+                if !matches_any(domain, &self.settings.allowed_domains) {
+                    return Err(format!(
+                        "Only addresses at {} can subscribe.",
+                        self.settings.allowed_domains.join(", ")
+                    ));
+                }
+            }
+            DomainPolicyMode::Blocklist => {
+                if matches_any(domain, DISPOSABLE_DOMAINS)
+                    || matches_any(domain, &self.settings.blocked_domains)
+                {
+                    return Err(format!(
+                        "Addresses at {} are not accepted. Please use a \
+                        permanent email address.",
+                        domain
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `domain` is one of `domains`, or a subdomain of one.
+fn matches_any(domain: &str, domains: &[impl AsRef<str>]) -> bool {
+    domains.iter().any(|candidate| {
+        let candidate = candidate.as_ref().trim().to_lowercase();
+        domain == candidate
+            || domain
+                .strip_suffix(&candidate)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// The common domain `domain` is one typo away from, if any.
+fn suggest_domain(domain: &str) -> Option<&'static str> {
+    if COMMON_DOMAINS.contains(&domain) {
+        return None;
+    }
+    COMMON_DOMAINS
+        .iter()
+        .find(|common| edit_distance(domain, common) == 1)
+        .copied()
+}
+
+/// The optimal string alignment distance: insertions, deletions,
+/// substitutions and transpositions of adjacent characters all count as one
+/// edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailPolicy, MxResolver, edit_distance};
+    use crate::{
+        configuration::{DomainPolicyMode, EmailPolicySettings},
+        domain::SubscriberEmail,
+    };
+    use claims::{assert_err, assert_ok};
+    use std::sync::Arc;
+
+    /// Only `gmail.com` has a mail server.
+    struct StubResolver;
+
+    #[async_trait::async_trait]
+    impl MxResolver for StubResolver {
+        async fn accepts_mail(
+            &self,
+            domain: &str,
+        ) -> Result<bool, anyhow::Error> {
+            match domain {
+                "gmail.com" => Ok(true),
+                "flaky.com" => Err(anyhow::anyhow!("Timed out.")),
+                _ => Ok(false),
+            }
+        }
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn policy() -> EmailPolicy {
+        EmailPolicy::new(EmailPolicySettings::default())
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_as_one_edit() {
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gmal.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gnail.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("example.com", "gmail.com"), 5);
+        assert_eq!(edit_distance("gmail.com", "gmail.com"), 0);
+    }
+
+    #[tokio::test]
+    async fn ordinary_addresses_are_accepted() {
+        assert_ok!(policy().check(&email("ursula@gmail.com")).await);
+        assert_ok!(policy().check(&email("ursula@example.com")).await);
+        assert_ok!(policy().check(&email("ursula@mail.com")).await);
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_and_their_subdomains_are_rejected() {
+        assert_err!(policy().check(&email("ursula@mailinator.com")).await);
+        assert_err!(policy().check(&email("ursula@eu.Mailinator.com")).await);
+        assert_ok!(policy().check(&email("ursula@notmailinator.com")).await);
+    }
+
+    #[tokio::test]
+    async fn configured_domains_are_blocked_too() {
+        let policy = EmailPolicy::new(EmailPolicySettings {
+            blocked_domains: vec!["spam.example".into()],
+            ..Default::default()
+        });
+
+        assert_err!(policy.check(&email("ursula@spam.example")).await);
+    }
+
+    #[test]
+    fn typos_of_common_domains_suggest_a_correction() {
+        let suggestion =
+            policy().suggest_correction(&email("ursula@gmial.com"));
+
+        assert_eq!(suggestion.as_deref(), Some("ursula@gmail.com"));
+    }
+
+    #[tokio::test]
+    async fn near_misses_of_common_domains_are_not_refused() {
+        // `ge.com`, `gm.com` and `aon.com` are real domains, one edit away
+        // from `me.com`, `gmx.com` and `aol.com`.
+        for address in ["jack@ge.com", "mary@gm.com", "ursula@aon.com"] {
+            assert!(policy().suggest_correction(&email(address)).is_some());
+            assert_ok!(policy().check(&email(address)).await);
+        }
+    }
+
+    #[test]
+    fn corrections_can_be_turned_off() {
+        let policy = EmailPolicy::new(EmailPolicySettings {
+            suggest_corrections: false,
+            ..Default::default()
+        });
+
+        assert_eq!(policy.suggest_correction(&email("ursula@gmial.com")), None);
+    }
+
+    #[tokio::test]
+    async fn allowlist_mode_only_accepts_listed_domains() {
+        let policy = EmailPolicy::new(EmailPolicySettings {
+            mode: DomainPolicyMode::Allowlist,
+            allowed_domains: vec!["example.com".into()],
+            ..Default::default()
+        });
+
+        assert_ok!(policy.check(&email("ursula@example.com")).await);
+        assert_ok!(policy.check(&email("ursula@mail.example.com")).await);
+        assert_err!(policy.check(&email("ursula@gmail.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domains_without_a_mail_server_are_rejected() {
+        let policy = EmailPolicy::with_resolver(
+            EmailPolicySettings::default(),
+            Arc::new(StubResolver),
+        );
+
+        assert_ok!(policy.check(&email("ursula@gmail.com")).await);
+        assert_err!(policy.check(&email("ursula@nomail.com")).await);
+    }
+
+    #[tokio::test]
+    async fn failed_mx_lookups_let_the_address_through() {
+        let policy = EmailPolicy::with_resolver(
+            EmailPolicySettings::default(),
+            Arc::new(StubResolver),
+        );
+
+        assert_ok!(policy.check(&email("ursula@flaky.com")).await);
+    }
+}
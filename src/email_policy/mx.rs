@@ -0,0 +1,62 @@
+use anyhow::Context;
+use hickory_resolver::{
+    TokioAsyncResolver,
+    config::{ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+};
+
+/// Looks up where a domain's mail goes. Stubbed out in tests.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    /// Whether `domain` has somewhere to deliver mail to.
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Asks DNS.
+pub struct DnsMxResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsMxResolver {
+    /// Use the system's resolvers, or Google's public ones if they cannot be
+    /// read.
+    pub fn from_system_conf() -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .unwrap_or_else(|e| {
+                tracing::warn!(
+                    error.message = %e,
+                    "Failed to read the system DNS configuration."
+                );
+                TokioAsyncResolver::tokio(
+                    ResolverConfig::default(),
+                    ResolverOpts::default(),
+                )
+            });
+        Self { resolver }
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for DnsMxResolver {
+    async fn accepts_mail(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // Rooted, so that the system's search domains are not appended.
+        let domain = format!("{}.", domain.trim_end_matches('.'));
+        match self.resolver.mx_lookup(domain.as_str()).await {
+            // A lone `.` exchange is a null MX: the domain takes no mail.
+            Ok(mx) => Ok(mx.iter().any(|record| !record.exchange().is_root())),
+            // Without MX records, mail goes to the domain's own address.
+            Err(e) if no_records_found(&e) => {
+                match self.resolver.lookup_ip(domain.as_str()).await {
+                    Ok(ips) => Ok(ips.iter().next().is_some()),
+                    Err(e) if no_records_found(&e) => Ok(false),
+                    Err(e) => Err(e).context("Failed to look up A records."),
+                }
+            }
+            Err(e) => Err(e).context("Failed to look up MX records."),
+        }
+    }
+}
+
+fn no_records_found(e: &ResolveError) -> bool {
+    matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
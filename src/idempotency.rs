@@ -0,0 +1,7 @@
+//! src/idempotency.rs
+
+mod key;
+mod persistence;
+
+pub use key::IdempotencyKey;
+pub use persistence::{NextAction, save_response, try_processing};
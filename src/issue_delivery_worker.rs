@@ -0,0 +1,449 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::Arc,
+    time::Duration,
+};
+
+use rand::Rng;
+use secrecy::SecretString;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::{EmailFormat, SubscriberEmail},
+    email_client::{
+        EmailError, EmailSender, OutgoingEmail, SenderIdentity, SentEmail,
+    },
+    mailing_list::{MailingList, get_list_by_id},
+    startup::get_connection_pool,
+    subscriber_token::list_unsubscribe_link,
+};
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// How many queued deliveries are locked and sent together.
+const MAX_TASKS_PER_BATCH: i64 = 100;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+struct Recipient {
+    id: Uuid,
+    email_format: EmailFormat,
+}
+
+struct NewsletterIssue {
+    list_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration.email_client.client();
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: String,
+    hmac_secret: SecretString,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            email_client.as_ref(),
+            &base_url,
+            &hmac_secret,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Deliver the newsletter issue to a batch of queued subscribers.
+///
+/// The queue rows stay locked for the duration of the delivery, so that
+/// several workers can drain the queue concurrently without sending the
+/// same email twice. The batch is handed to the email client in one go, and
+/// each task is then settled on its own: transient failures are retried with
+/// exponential backoff; permanent failures and exhausted retries are
+/// dead-lettered.
+#[tracing::instrument(skip_all, fields(n_tasks=tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    hmac_secret: &SecretString,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, tasks) = dequeue_tasks(pool).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+
+    let mut issues = HashMap::new();
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let email = match SubscriberEmail::parse(task.subscriber_email.clone())
+        {
+            Ok(email) => email,
+            Err(e) => {
+                tracing::error!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    error.message = %e,
+                    "Dead-lettering a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                dead_letter_task(&mut transaction, &task, &e).await?;
+                continue;
+            }
+        };
+
+        if let Entry::Vacant(entry) = issues.entry(task.newsletter_issue_id) {
+            entry.insert(get_issue(pool, task.newsletter_issue_id).await?);
+        }
+        let (issue, list, _) = &issues[&task.newsletter_issue_id];
+
+        // The subscriber may have unsubscribed or paused delivery since the
+        // issue was enqueued.
+        let Some(recipient) =
+            get_active_recipient(pool, &email, issue.list_id).await?
+        else {
+            tracing::info!(
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                "Skipping a subscriber who is no longer confirmed or has \
+                paused delivery."
+            );
+            delete_task(&mut transaction, &task).await?;
+            continue;
+        };
+        let unsubscribe_url = list_unsubscribe_link(
+            base_url,
+            recipient.id,
+            &list.slug,
+            hmac_secret,
+        );
+        deliveries.push((task, email, recipient.email_format, unsubscribe_url));
+    }
+
+    let emails: Vec<_> = deliveries
+        .iter()
+        .map(|(task, email, email_format, unsubscribe_url)| {
+            let (issue, _, sender) = &issues[&task.newsletter_issue_id];
+            let html_body = match email_format {
+                EmailFormat::Html => issue.html_content.as_str(),
+                EmailFormat::Text => "",
+            };
+            OutgoingEmail {
+                recipient: email,
+                subject: &issue.title,
+                html_body,
+                text_body: &issue.text_content,
+                unsubscribe_url: Some(unsubscribe_url),
+                sender: sender.as_ref(),
+            }
+        })
+        .collect();
+    let results = email_client.send_batch(&emails).await;
+
+    for ((task, ..), result) in deliveries.iter().zip(results) {
+        settle_task(&mut transaction, task, result).await?;
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Record the outcome of a single delivery attempt.
+async fn settle_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    result: Result<SentEmail, EmailError>,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_retries + 1;
+    match result {
+        Ok(sent) => {
+            record_delivery(transaction, task, sent.message_id.as_deref()).await
+        }
+        Err(e) if e.is_transient() && n_attempts < MAX_DELIVERY_ATTEMPTS => {
+            let delay = retry_delay(task.n_retries);
+            tracing::warn!(
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                n_retries = task.n_retries,
+                error.cause_chain = ?e,
+                error.message = %e,
+                retry_in_seconds = delay.as_secs(),
+                "Failed to deliver issue to a confirmed subscriber. \
+                Retrying later.",
+            );
+            schedule_retry(transaction, task, delay).await
+        }
+        Err(e) => {
+            tracing::error!(
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                n_retries = task.n_retries,
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. \
+                Dead-lettering.",
+            );
+            dead_letter_task(transaction, task, &format!("{e:?}")).await
+        }
+    }
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of
+/// `BASE_RETRY_DELAY * 2^n_retries`, capped at `MAX_RETRY_DELAY`.
+fn retry_delay(n_retries: i16) -> Duration {
+    let exponent = n_retries.clamp(0, 16) as u32;
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    pool: &PgPool,
+) -> Result<(PgTransaction, Vec<Task>), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+        select newsletter_issue_id, subscriber_email, n_retries
+        from issue_delivery_queue
+        where execute_after <= now()
+        order by execute_after
+        for update
+        skip locked
+        limit $1
+        "#,
+        MAX_TASKS_PER_BATCH
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        delete from issue_delivery_queue
+        where
+            newsletter_issue_id = $1 and
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    provider_message_id: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        insert into issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            provider_message_id,
+            delivered_at
+        )
+        values ($1, $2, $3, now())
+        on conflict (newsletter_issue_id, subscriber_email) do update
+        set
+            provider_message_id = excluded.provider_message_id,
+            delivered_at = excluded.delivered_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        provider_message_id
+    );
+    transaction.execute(query).await?;
+    delete_task(transaction, task).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        update issue_delivery_queue
+        set
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        where
+            newsletter_issue_id = $1 and
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        insert into issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            last_error,
+            failed_at
+        )
+        values ($1, $2, $3, $4, now())
+        on conflict (newsletter_issue_id, subscriber_email) do update
+        set
+            n_attempts = excluded.n_attempts,
+            last_error = excluded.last_error,
+            failed_at = excluded.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries + 1,
+        last_error
+    );
+    transaction.execute(query).await?;
+    delete_task(transaction, task).await
+}
+
+/// The subscriber behind `email`, if they are confirmed on the list and not
+/// paused.
+#[tracing::instrument(skip_all)]
+async fn get_active_recipient(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    list_id: Uuid,
+) -> Result<Option<Recipient>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        select s.id, s.email_format
+        from subscriptions s
+        join list_subscriptions l on l.subscriber_id = s.id
+        where
+            s.email_normalized = $1 and
+            s.status = 'confirmed' and
+            (s.paused_until is null or s.paused_until <= now()) and
+            l.list_id = $2 and
+            l.status = 'confirmed'
+        "#,
+        email.normalized(),
+        list_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    row.map(|r| {
+        Ok(Recipient {
+            id: r.id,
+            email_format: r
+                .email_format
+                .try_into()
+                .map_err(anyhow::Error::msg)?,
+        })
+    })
+    .transpose()
+}
+
+/// The issue, the list it was published to and who it is sent from.
+#[tracing::instrument(skip_all)]
+async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<(NewsletterIssue, MailingList, Option<SenderIdentity>), anyhow::Error>
+{
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        select list_id, title, text_content, html_content
+        from newsletter_issues
+        where newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let list = get_list_by_id(pool, issue.list_id).await?;
+    let sender = list.sender();
+    Ok((issue, list, sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BASE_RETRY_DELAY, MAX_RETRY_DELAY, retry_delay};
+
+    #[test]
+    fn the_first_retry_waits_at_most_the_base_delay() {
+        let delay = retry_delay(0);
+        assert!(delay >= BASE_RETRY_DELAY / 2);
+        assert!(delay <= BASE_RETRY_DELAY);
+    }
+
+    #[test]
+    fn retry_delays_grow_exponentially() {
+        let delay = retry_delay(3);
+        assert!(delay >= BASE_RETRY_DELAY * 4);
+        assert!(delay <= BASE_RETRY_DELAY * 8);
+    }
+
+    #[test]
+    fn retry_delays_are_capped() {
+        for n_retries in [10, 16, i16::MAX] {
+            assert!(retry_delay(n_retries) <= MAX_RETRY_DELAY);
+        }
+    }
+}
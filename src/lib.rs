@@ -1,7 +1,21 @@
 //! src/lib.rs
+pub mod api_error;
+pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_normalization;
+pub mod email_policy;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod mailing_list;
+pub mod rate_limit;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
+pub mod signup_protection;
 pub mod startup;
+pub mod subscriber_data;
+pub mod subscriber_token;
 pub mod telemetry;
+pub mod utils;
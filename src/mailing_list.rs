@@ -0,0 +1,159 @@
+//! Mailing lists, each with its own name, sender identity and confirmation
+//! email. An address is confirmed separately for every list it joins.
+use sqlx::{Executor, Postgres};
+use uuid::Uuid;
+
+use crate::{domain::SubscriberEmail, email_client::SenderIdentity};
+
+#[derive(Debug, serde::Serialize)]
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub sender_name: Option<String>,
+    pub sender_email: Option<String>,
+    pub confirmation_subject: String,
+    pub confirmation_message: String,
+    pub is_default: bool,
+}
+
+impl MailingList {
+    /// Where the list's subscription URLs live. The default list keeps the
+    /// unscoped `/subscriptions` ones, so links sent before there were
+    /// several lists keep working.
+    pub fn path_prefix(&self) -> String {
+        if self.is_default {
+            String::new()
+        } else {
+            format!("/lists/{}", self.slug)
+        }
+    }
+
+    /// Who the list's emails come from, if not the configured sender.
+    pub fn sender(&self) -> Option<SenderIdentity> {
+        if self.sender_name.is_none() && self.sender_email.is_none() {
+            return None;
+        }
+        Some(SenderIdentity {
+            name: self.sender_name.clone(),
+            // Checked when the list was created.
+            email: self
+                .sender_email
+                .clone()
+                .and_then(|email| SubscriberEmail::parse(email).ok()),
+        })
+    }
+}
+
+/// The list called `slug`, or the default list if there is no `slug`.
+#[tracing::instrument(skip(executor))]
+pub async fn get_list<'a, E>(
+    executor: E,
+    slug: Option<&str>,
+) -> Result<Option<MailingList>, sqlx::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        select
+            list_id, slug, name, sender_name, sender_email,
+            confirmation_subject, confirmation_message, is_default
+        from lists
+        where slug = $1 or ($1 is null and is_default)
+        "#,
+        slug
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn get_list_by_id<'a, E>(
+    executor: E,
+    list_id: Uuid,
+) -> Result<MailingList, sqlx::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        select
+            list_id, slug, name, sender_name, sender_email,
+            confirmation_subject, confirmation_message, is_default
+        from lists
+        where list_id = $1
+        "#,
+        list_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Every list, oldest first.
+#[tracing::instrument(skip(executor))]
+pub async fn get_all_lists<'a, E>(
+    executor: E,
+) -> Result<Vec<MailingList>, sqlx::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        select
+            list_id, slug, name, sender_name, sender_email,
+            confirmation_subject, confirmation_message, is_default
+        from lists
+        order by created_at, slug
+        "#
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// A list as an admin asked for it, already validated.
+pub struct NewMailingList {
+    pub slug: String,
+    pub name: String,
+    pub sender_name: Option<String>,
+    pub sender_email: Option<SubscriberEmail>,
+    pub confirmation_subject: String,
+    pub confirmation_message: String,
+}
+
+/// Store a new list. Returns `None` if the slug is taken.
+#[tracing::instrument(skip(executor, list), fields(slug = %list.slug))]
+pub async fn insert_list<'a, E>(
+    executor: E,
+    list: &NewMailingList,
+) -> Result<Option<MailingList>, sqlx::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        insert into lists (
+            list_id, slug, name, sender_name, sender_email,
+            confirmation_subject, confirmation_message, is_default, created_at
+        )
+        values ($1, $2, $3, $4, $5, $6, $7, false, now())
+        on conflict (slug) do nothing
+        returning
+            list_id, slug, name, sender_name, sender_email,
+            confirmation_subject, confirmation_message, is_default
+        "#,
+        Uuid::new_v4(),
+        list.slug,
+        list.name,
+        list.sender_name,
+        list.sender_email.as_ref().map(|e| e.as_ref()),
+        list.confirmation_subject,
+        list.confirmation_message
+    )
+    .fetch_optional(executor)
+    .await
+}
@@ -1,22 +1,60 @@
 //! src/main.rs
 
-use telemetry::init_subscriber;
-use zero2prod::{configuration::get_configuration, startup::Application};
-mod telemetry;
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
+use zero2prod::{
+    configuration::get_configuration,
+    issue_delivery_worker::run_worker_until_stopped,
+    startup::Application,
+    telemetry::{get_subscriber, init_subscriber},
+};
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let subscriber = telemetry::get_subscriber(
-        "zero2prod".into(),
-        "info".into(),
-        std::io::stdout,
-    );
+async fn main() -> anyhow::Result<()> {
+    let subscriber =
+        get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
 
     let configuration =
         get_configuration().expect("Failed to read configuration.");
 
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let application = Application::build(configuration.clone()).await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+
+    // Both tasks are expected to run forever: bail out if either stops.
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+    };
+
     Ok(())
 }
+
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>,
+) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
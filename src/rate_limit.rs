@@ -0,0 +1,285 @@
+//! Token-bucket rate limiting for the endpoints that send confirmation
+//! emails, keyed by client IP and by the email address in the request.
+mod memory;
+mod postgres;
+
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{
+    HttpResponse, ResponseError,
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+pub use memory::InMemoryStore;
+pub use postgres::PostgresStore;
+
+use crate::{
+    api_error::too_many_requests_response,
+    configuration::{RateLimitBackend, RateLimitSettings, TokenBucketSettings},
+    domain::normalize_email,
+};
+
+/// The state of one bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The outcome of trying to take a token from a bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl Bucket {
+    pub fn full(settings: &TokenBucketSettings, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: settings.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    /// How many tokens the bucket holds at `now`, once refilled for the time
+    /// elapsed since it was last updated.
+    pub fn tokens_at(
+        &self,
+        settings: &TokenBucketSettings,
+        now: DateTime<Utc>,
+    ) -> f64 {
+        let interval = settings.refill_interval_seconds as f64;
+        let elapsed =
+            (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        (self.tokens + elapsed / interval).min(settings.capacity as f64)
+    }
+
+    /// Refill the bucket, then try to take a token out of it.
+    pub fn take(
+        self,
+        settings: &TokenBucketSettings,
+        now: DateTime<Utc>,
+    ) -> (Self, Decision) {
+        let interval = settings.refill_interval_seconds as f64;
+        let tokens = self.tokens_at(settings, now);
+
+        if tokens >= 1.0 {
+            let bucket = Self {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            (bucket, Decision::Allowed)
+        } else {
+            let bucket = Self {
+                tokens,
+                updated_at: now,
+            };
+            let retry_after =
+                Duration::from_secs_f64((1.0 - tokens) * interval);
+            (bucket, Decision::Limited { retry_after })
+        }
+    }
+}
+
+/// Where buckets are kept between requests.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Take a token from the bucket stored under `key`, creating a full one
+    /// if there is none yet.
+    async fn take(
+        &self,
+        key: &str,
+        settings: &TokenBucketSettings,
+    ) -> Result<Decision, anyhow::Error>;
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Too many requests, retry in {retry_after:?}.")]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl ResponseError for RateLimited {
+    fn error_response(&self) -> HttpResponse {
+        too_many_requests_response(
+            "Too many requests, please slow down.",
+            self.retry_after,
+        )
+    }
+}
+
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    settings: RateLimitSettings,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings, pool: PgPool) -> Self {
+        let store: Arc<dyn RateLimitStore> = match settings.backend {
+            RateLimitBackend::Memory => Arc::new(InMemoryStore::default()),
+            RateLimitBackend::Postgres => Arc::new(PostgresStore::new(pool)),
+        };
+        Self { store, settings }
+    }
+
+    /// Charge the request to the client's bucket, then to the target
+    /// address's bucket.
+    ///
+    /// A failing store lets the request through: losing rate limiting for a
+    /// while is better than refusing every subscription.
+    pub async fn check(
+        &self,
+        client_ip: Option<&str>,
+        email: Option<&str>,
+    ) -> Result<(), RateLimited> {
+        let buckets = [
+            client_ip.map(|ip| (format!("ip:{}", ip), &self.settings.per_ip)),
+            email.map(|email| {
+                (format!("email:{}", email), &self.settings.per_email)
+            }),
+        ];
+        for (key, settings) in buckets.into_iter().flatten() {
+            match self.store.take(&key, settings).await {
+                Ok(Decision::Allowed) => {}
+                Ok(Decision::Limited { retry_after }) => {
+                    return Err(RateLimited { retry_after });
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to check a rate limit bucket."
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TargetEmail {
+    email: String,
+}
+
+/// Rate limit requests to the wrapped resource.
+///
+/// The body is read here to find the target email address, then handed back
+/// untouched to the handler.
+pub async fn rate_limit(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("The rate limiter is not registered as app data.")
+        .clone();
+
+    let client_ip = {
+        let connection_info = req.connection_info();
+        if limiter.settings.trust_forwarded_headers {
+            connection_info.realip_remote_addr().map(ToOwned::to_owned)
+        } else {
+            connection_info.peer_addr().map(ToOwned::to_owned)
+        }
+    };
+    let body = req.extract::<web::Bytes>().await?;
+    let email = target_email(&body);
+    req.set_payload(Payload::from(body));
+
+    limiter
+        .check(client_ip.as_deref(), email.as_deref())
+        .await?;
+    next.call(req).await
+}
+
+/// The normalised `email` field of a JSON or form-encoded body.
+fn target_email(body: &[u8]) -> Option<String> {
+    serde_json::from_slice::<TargetEmail>(body)
+        .ok()
+        .or_else(|| serde_urlencoded::from_bytes::<TargetEmail>(body).ok())
+        .map(|target| normalize_email(&target.email))
+        .filter(|email| !email.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bucket, Decision, target_email};
+    use crate::configuration::TokenBucketSettings;
+    use chrono::{TimeDelta, Utc};
+    use std::time::Duration;
+
+    const SETTINGS: TokenBucketSettings = TokenBucketSettings {
+        capacity: 2,
+        refill_interval_seconds: 10,
+    };
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let now = Utc::now();
+        let bucket = Bucket::full(&SETTINGS, now);
+
+        let (bucket, first) = bucket.take(&SETTINGS, now);
+        let (bucket, second) = bucket.take(&SETTINGS, now);
+        let (_, third) = bucket.take(&SETTINGS, now);
+
+        assert_eq!(first, Decision::Allowed);
+        assert_eq!(second, Decision::Allowed);
+        assert_eq!(
+            third,
+            Decision::Limited {
+                retry_after: Duration::from_secs(10)
+            }
+        );
+    }
+
+    #[test]
+    fn an_empty_bucket_refills_over_time() {
+        let now = Utc::now();
+        let empty = Bucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        let (_, early) = empty.take(&SETTINGS, now + TimeDelta::seconds(4));
+        let (_, on_time) = empty.take(&SETTINGS, now + TimeDelta::seconds(10));
+
+        assert_eq!(
+            early,
+            Decision::Limited {
+                retry_after: Duration::from_secs(6)
+            }
+        );
+        assert_eq!(on_time, Decision::Allowed);
+    }
+
+    #[test]
+    fn a_bucket_never_holds_more_than_its_capacity() {
+        let now = Utc::now();
+        let bucket = Bucket::full(&SETTINGS, now);
+
+        let (bucket, _) = bucket.take(&SETTINGS, now + TimeDelta::days(1));
+
+        assert_eq!(bucket.tokens, 1.0);
+    }
+
+    #[test]
+    fn the_target_email_is_read_from_json_and_forms() {
+        assert_eq!(
+            target_email(
+                br#"{"name": "le guin", "email": "Ursula@Gmail.com"}"#
+            ),
+            Some("ursula@gmail.com".into())
+        );
+        assert_eq!(
+            target_email(b"name=le%20guin&email=ursula%40gmail.com"),
+            Some("ursula@gmail.com".into())
+        );
+        assert_eq!(target_email(b"name=le%20guin"), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+
+use super::{Bucket, Decision, RateLimitStore};
+use crate::configuration::TokenBucketSettings;
+
+/// At most this many buckets are kept. Past it, full ones are dropped first:
+/// they hold no information a fresh bucket would not. Then the least recently
+/// used go.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Buckets kept in process memory. Limits are per instance and reset on
+/// restart.
+pub struct InMemoryStore {
+    buckets: Mutex<HashMap<String, (Bucket, TokenBucketSettings)>>,
+    max_buckets: usize,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::with_max_buckets(MAX_BUCKETS)
+    }
+}
+
+impl InMemoryStore {
+    fn with_max_buckets(max_buckets: usize) -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+            max_buckets,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn take(
+        &self,
+        key: &str,
+        settings: &TokenBucketSettings,
+    ) -> Result<Decision, anyhow::Error> {
+        let now = Utc::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= self.max_buckets && !buckets.contains_key(key) {
+            evict(&mut buckets, self.max_buckets, now);
+        }
+
+        let bucket = buckets
+            .get(key)
+            .map(|(bucket, _)| *bucket)
+            .unwrap_or_else(|| Bucket::full(settings, now));
+        let (bucket, decision) = bucket.take(settings, now);
+        buckets.insert(key.to_owned(), (bucket, *settings));
+        Ok(decision)
+    }
+}
+
+/// Make room for new buckets, down to nine tenths of `max_buckets` so that
+/// the next few new keys do not each scan the whole map again.
+fn evict(
+    buckets: &mut HashMap<String, (Bucket, TokenBucketSettings)>,
+    max_buckets: usize,
+    now: DateTime<Utc>,
+) {
+    buckets.retain(|_, (bucket, settings)| {
+        bucket.tokens_at(settings, now) < settings.capacity as f64
+    });
+
+    let target = (max_buckets - max_buckets / 10).saturating_sub(1);
+    if buckets.len() <= target {
+        return;
+    }
+    let mut by_age: Vec<(DateTime<Utc>, String)> = buckets
+        .iter()
+        .map(|(key, (bucket, _))| (bucket.updated_at, key.clone()))
+        .collect();
+    let excess = by_age.len() - target;
+    by_age.select_nth_unstable(excess - 1);
+    for (_, key) in &by_age[..excess] {
+        buckets.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryStore;
+    use crate::{
+        configuration::TokenBucketSettings, rate_limit::RateLimitStore,
+    };
+
+    const SETTINGS: TokenBucketSettings = TokenBucketSettings {
+        capacity: 1,
+        refill_interval_seconds: 3600,
+    };
+
+    #[tokio::test]
+    async fn the_oldest_buckets_are_evicted_past_the_cap() {
+        let store = InMemoryStore::with_max_buckets(10);
+
+        // Every bucket is left empty, so none can be dropped for being full.
+        for i in 0..25 {
+            store.take(&format!("key-{}", i), &SETTINGS).await.unwrap();
+        }
+
+        let buckets = store.buckets.lock().unwrap();
+        assert!(buckets.len() <= 10);
+        assert!(buckets.contains_key("key-24"));
+        assert!(!buckets.contains_key("key-0"));
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Context;
+use chrono::{TimeDelta, Utc};
+use rand::Rng;
+use sqlx::PgPool;
+
+use super::{Bucket, Decision, RateLimitStore};
+use crate::configuration::TokenBucketSettings;
+
+/// Buckets untouched for this long are deleted.
+const STALE_AFTER: TimeDelta = TimeDelta::days(1);
+
+/// Buckets kept in the `rate_limit_buckets` table, shared by every instance
+/// of the application.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn take(
+        &self,
+        key: &str,
+        settings: &TokenBucketSettings,
+    ) -> Result<Decision, anyhow::Error> {
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await.context(
+            "Failed to acquire a Postgres connection from the pool.",
+        )?;
+
+        let full = Bucket::full(settings, now);
+        sqlx::query!(
+            r#"
+            insert into rate_limit_buckets (key, tokens, updated_at)
+            values ($1, $2, $3)
+            on conflict (key) do nothing
+            "#,
+            key,
+            full.tokens,
+            full.updated_at
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to create the rate limit bucket.")?;
+        // The row lock keeps concurrent requests from spending the same token.
+        let bucket = sqlx::query_as!(
+            Bucket,
+            r#"
+            select tokens, updated_at from rate_limit_buckets
+            where key = $1
+            for update
+            "#,
+            key
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to fetch the rate limit bucket.")?;
+
+        let (bucket, decision) = bucket.take(settings, now);
+        sqlx::query!(
+            r#"
+            update rate_limit_buckets
+            set tokens = $2, updated_at = $3
+            where key = $1
+            "#,
+            key,
+            bucket.tokens,
+            bucket.updated_at
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update the rate limit bucket.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the rate limit bucket.")?;
+
+        // Every now and then, sweep buckets nobody has used in a while.
+        if rand::thread_rng().gen_bool(0.01) {
+            sqlx::query!(
+                "delete from rate_limit_buckets where updated_at < $1",
+                now - STALE_AFTER
+            )
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete stale rate limit buckets.")?;
+        }
+
+        Ok(decision)
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_attempts: i16,
+    last_error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+pub async fn dead_letters(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let dead_letters = get_dead_letters(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for d in &dead_letters {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{title}</td>
+            <td>{email}</td>
+            <td>{n_attempts}</td>
+            <td>{last_error}</td>
+            <td>{failed_at}</td>
+            <td>
+                <form action="/admin/dead_letters/requeue" method="post">
+                    <input type="hidden" name="newsletter_issue_id" value="{issue_id}">
+                    <input type="hidden" name="subscriber_email" value="{email_attribute}">
+                    <button type="submit">Requeue</button>
+                </form>
+            </td>
+        </tr>"#,
+            title = htmlescape::encode_minimal(&d.title),
+            email = htmlescape::encode_minimal(&d.subscriber_email),
+            email_attribute = htmlescape::encode_attribute(&d.subscriber_email),
+            n_attempts = d.n_attempts,
+            last_error = htmlescape::encode_minimal(&d.last_error),
+            failed_at = d.failed_at.to_rfc3339(),
+            issue_id = d.newsletter_issue_id,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Failed deliveries</title>
+</head>
+<body>
+    {msg_html}
+    <p>{count} failed deliveries.</p>
+    <table>
+        <tr>
+            <th>Issue</th>
+            <th>Subscriber</th>
+            <th>Attempts</th>
+            <th>Last error</th>
+            <th>Failed at</th>
+            <th></th>
+        </tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            count = dead_letters.len(),
+        )))
+}
+
+pub async fn requeue_dead_letter(
+    form: web::Form<RequeueFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued =
+        requeue(&pool, form.0.newsletter_issue_id, &form.0.subscriber_email)
+            .await
+            .map_err(e500)?;
+
+    if requeued {
+        FlashMessage::info("The delivery has been requeued.").send();
+    } else {
+        FlashMessage::error("The failed delivery could not be found.").send();
+    }
+    Ok(see_other("/admin/dead_letters"))
+}
+
+#[tracing::instrument(name = "Get dead-lettered deliveries", skip(pool))]
+async fn get_dead_letters(
+    pool: &PgPool,
+) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        select
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_attempts,
+            d.last_error,
+            d.failed_at
+        from issue_delivery_dead_letters d
+        join newsletter_issues i using (newsletter_issue_id)
+        order by d.failed_at desc
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead-lettered deliveries.")?;
+    Ok(dead_letters)
+}
+
+/// Move a dead-lettered delivery back onto the queue with a fresh retry
+/// budget. Returns `false` if there was nothing to requeue.
+#[tracing::instrument(name = "Requeue a dead-lettered delivery", skip(pool))]
+async fn requeue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let deleted = sqlx::query!(
+        r#"
+        delete from issue_delivery_dead_letters
+        where newsletter_issue_id = $1 and subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the dead-lettered delivery.")?
+    .rows_affected();
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        insert into issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        values ($1, $2)
+        on conflict do nothing
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enqueue the delivery.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the requeued delivery.")?;
+    Ok(true)
+}
@@ -0,0 +1,7 @@
+mod dashboard;
+mod dead_letters;
+mod logout;
+
+pub use dashboard::*;
+pub use dead_letters::*;
+pub use logout::*;
@@ -0,0 +1,80 @@
+use std::fmt::Write;
+
+use actix_web::{HttpResponse, http::header::ContentType, web};
+use anyhow::Context;
+use uuid::Uuid;
+
+use crate::{
+    email_client::read_outbox_index, startup::OutboxDirectory, utils::e500,
+};
+
+pub async fn outbox(
+    directory: web::Data<OutboxDirectory>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entries = read_outbox_index(&directory.0).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for e in entries.iter().rev() {
+        writeln!(
+            rows_html,
+            r#"<tr>
+            <td>{captured_at}</td>
+            <td>{from}</td>
+            <td>{to}</td>
+            <td><a href="/dev/outbox/{id}">{subject}</a></td>
+        </tr>"#,
+            captured_at = e.captured_at.to_rfc3339(),
+            from = htmlescape::encode_minimal(&e.from),
+            to = htmlescape::encode_minimal(&e.to),
+            id = e.id,
+            subject = htmlescape::encode_minimal(&e.subject),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Outbox</title>
+</head>
+<body>
+    <p>{count} captured emails in {directory}.</p>
+    <table>
+        <tr>
+            <th>Captured at</th>
+            <th>From</th>
+            <th>To</th>
+            <th>Subject</th>
+        </tr>
+        {rows_html}
+    </table>
+</body>
+</html>"#,
+            count = entries.len(),
+            directory =
+                htmlescape::encode_minimal(&directory.0.display().to_string()),
+        )))
+}
+
+pub async fn outbox_message(
+    directory: web::Data<OutboxDirectory>,
+    id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entries = read_outbox_index(&directory.0).await.map_err(e500)?;
+    let Some(entry) = entries.iter().find(|e| e.id == *id) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let message = tokio::fs::read_to_string(directory.0.join(&entry.file))
+        .await
+        .context("Failed to read the message from the outbox.")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(message))
+}
@@ -0,0 +1,204 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError, http::StatusCode, web,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    api_error::{
+        FieldError, describe_field_errors, error_response,
+        internal_error_response, unauthorized_response,
+    },
+    authentication::{AuthError, authenticate_basic},
+    domain::SubscriberEmail,
+    mailing_list::{NewMailingList, get_all_lists, insert_list},
+    routes::error_chain_fmt,
+};
+
+const MAX_SLUG_LENGTH: usize = 64;
+const MAX_TEXT_LENGTH: usize = 256;
+
+#[derive(serde::Deserialize)]
+pub struct ListData {
+    slug: String,
+    name: String,
+    #[serde(default)]
+    sender_name: Option<String>,
+    #[serde(default)]
+    sender_email: Option<String>,
+    #[serde(default)]
+    confirmation_subject: Option<String>,
+    #[serde(default)]
+    confirmation_message: Option<String>,
+}
+
+impl TryFrom<ListData> for NewMailingList {
+    type Error = Vec<FieldError>;
+
+    fn try_from(data: ListData) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+        if data.slug.is_empty()
+            || data.slug.len() > MAX_SLUG_LENGTH
+            || !data.slug.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'
+            })
+        {
+            errors.push(FieldError::new(
+                "slug",
+                format!(
+                    "must be 1 to {} lowercase letters, digits or dashes",
+                    MAX_SLUG_LENGTH
+                ),
+            ));
+        }
+        if let Err(e) = check_text(&data.name) {
+            errors.push(FieldError::new("name", e));
+        }
+        if let Some(sender_name) = &data.sender_name
+            && let Err(e) = check_text(sender_name)
+        {
+            errors.push(FieldError::new("sender_name", e));
+        }
+        let sender_email = match data.sender_email.map(SubscriberEmail::parse) {
+            Some(Err(e)) => {
+                errors.push(FieldError::new("sender_email", e));
+                None
+            }
+            Some(Ok(email)) => Some(email),
+            None => None,
+        };
+        if let Some(subject) = &data.confirmation_subject
+            && let Err(e) = check_text(subject)
+        {
+            errors.push(FieldError::new("confirmation_subject", e));
+        }
+        if let Some(message) = &data.confirmation_message
+            && message.trim().is_empty()
+        {
+            errors.push(FieldError::new(
+                "confirmation_message",
+                "must not be empty",
+            ));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(NewMailingList {
+            confirmation_subject: data.confirmation_subject.unwrap_or_else(
+                || format!("Confirm your subscription to {}", data.name),
+            ),
+            confirmation_message: data
+                .confirmation_message
+                .unwrap_or_else(|| format!("Welcome to {}!", data.name)),
+            slug: data.slug,
+            name: data.name,
+            sender_name: data.sender_name,
+            sender_email,
+        })
+    }
+}
+
+/// Names and subjects end up in email headers: they must fit on one line.
+fn check_text(s: &str) -> Result<(), String> {
+    if s.trim().is_empty() {
+        Err("must not be empty".into())
+    } else if s.graphemes(true).count() > MAX_TEXT_LENGTH {
+        Err(format!(
+            "must be at most {} characters long",
+            MAX_TEXT_LENGTH
+        ))
+    } else if s.chars().any(char::is_control) {
+        Err("must not contain control characters".into())
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ListsApiError {
+    #[error("{}", describe_field_errors(.0))]
+    ValidationError(Vec<FieldError>),
+    #[error("A list with this slug already exists.")]
+    Conflict,
+    #[error("Authentication failed.")]
+    AuthError(#[from] AuthError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListsApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ListsApiError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ListsApiError::ValidationError(details) => error_response(
+                StatusCode::BAD_REQUEST,
+                "validation_error",
+                "The submitted data is invalid.",
+                details,
+            ),
+            ListsApiError::Conflict => error_response(
+                StatusCode::CONFLICT,
+                "conflict",
+                &self.to_string(),
+                &[],
+            ),
+            ListsApiError::UnexpectedError(_) => internal_error_response(),
+            ListsApiError::AuthError(AuthError::InvalidCredentials(_)) => {
+                unauthorized_response("admin")
+            }
+            ListsApiError::AuthError(AuthError::UnexpectedError(_)) => {
+                internal_error_response()
+            }
+        }
+    }
+}
+
+/// `GET /api/v1/admin/lists`: every list, oldest first.
+#[tracing::instrument(
+    name = "List the mailing lists",
+    skip(pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn get_lists(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ListsApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+
+    let lists = get_all_lists(pool.get_ref())
+        .await
+        .context("Failed to fetch the mailing lists.")?;
+    Ok(HttpResponse::Ok().json(lists))
+}
+
+/// `POST /api/v1/admin/lists`: create a list. Its subscription URLs live
+/// under `/lists/{slug}`.
+#[tracing::instrument(
+    name = "Create a mailing list",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_list(
+    body: web::Json<ListData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ListsApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    let new_list: NewMailingList = body
+        .into_inner()
+        .try_into()
+        .map_err(ListsApiError::ValidationError)?;
+
+    let list = insert_list(pool.get_ref(), &new_list)
+        .await
+        .context("Failed to store the mailing list.")?
+        .ok_or(ListsApiError::Conflict)?;
+    Ok(HttpResponse::Created().json(list))
+}
@@ -1,11 +1,35 @@
+mod admin;
+mod dev_outbox;
 pub mod greet;
 mod health_check;
+mod lists_api;
+mod login;
 mod newsletter;
+mod subscriber_data_api;
 mod subscriptions;
+mod subscriptions_api;
+mod subscriptions_challenge;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_preferences;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
+mod webhooks;
 
+pub use admin::*;
+pub use dev_outbox::*;
 pub use greet::*;
 pub use health_check::*;
+pub use lists_api::*;
+pub use login::*;
 pub use newsletter::*;
+pub use subscriber_data_api::*;
 pub use subscriptions::*;
+pub use subscriptions_api::*;
+pub use subscriptions_challenge::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_data::*;
+pub use subscriptions_preferences::*;
+pub use subscriptions_resend::*;
+pub use subscriptions_unsubscribe::*;
+pub use webhooks::*;
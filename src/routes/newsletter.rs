@@ -1,14 +1,28 @@
 use crate::{
-    domain::SubscriberEmail, email_client::EmailClient, routes::error_chain_fmt,
+    api_error::{
+        error_response, internal_error_response, unauthorized_response,
+    },
+    authentication::{AuthError, authenticate_basic},
+    idempotency::{IdempotencyKey, NextAction, save_response, try_processing},
+    mailing_list::get_list,
+    routes::error_chain_fmt,
+};
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::{StatusCode, header::HeaderMap},
+    web,
 };
-use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
 use anyhow::Context;
-use sqlx::PgPool;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     pub title: String,
     pub content: Content,
+    /// The slug of the list to send the issue to; the default list if unset.
+    #[serde(default)]
+    pub list: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -17,12 +31,12 @@ pub struct Content {
     pub html: String,
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[from] AuthError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -34,62 +48,138 @@ impl std::fmt::Debug for PublishError {
 }
 
 impl ResponseError for PublishError {
-    fn status_code(&self) -> actix_web::http::StatusCode {
+    fn error_response(&self) -> HttpResponse {
         match self {
-            PublishError::UnexpectedError(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
+            PublishError::ValidationError(message) => error_response(
+                StatusCode::BAD_REQUEST,
+                "validation_error",
+                message,
+                &[],
+            ),
+            PublishError::UnexpectedError(_) => internal_error_response(),
+            PublishError::AuthError(AuthError::InvalidCredentials(_)) => {
+                unauthorized_response("publish")
+            }
+            PublishError::AuthError(AuthError::UnexpectedError(_)) => {
+                internal_error_response()
             }
         }
     }
 }
 
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to send newsletter issue to {}",
-                            subscriber.email
-                        )
-                    })?;
+    let user_id = authenticate_basic(request.headers(), &pool).await?;
+
+    let idempotency_key = idempotency_key(request.headers())
+        .map_err(PublishError::ValidationError)?;
+    let list = get_list(&**pool, body.list.as_deref())
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| {
+            PublishError::ValidationError("There is no such list.".into())
+        })?;
+    let mut transaction =
+        match try_processing(&pool, &idempotency_key, user_id).await? {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                return Ok(saved_response);
             }
-            Err(error) => tracing::warn!(
-                error.cause_chain = ?error,
-                "Skipping confirmed subscriber.  Their stored contact details are invalid",
-            ),
-        }
-    }
-    Ok(HttpResponse::Ok().finish())
-}
+        };
 
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"select email from subscriptions where status = 'confirmed'"#,
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        list.list_id,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
+    .await
+    .context("Failed to store newsletter issue details.")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+
+    let response = HttpResponse::Accepted().finish();
+    let response =
+        save_response(transaction, &idempotency_key, user_id, response).await?;
+    Ok(response)
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<IdempotencyKey, String> {
+    headers
+        .get("Idempotency-Key")
+        .ok_or_else(|| "The 'Idempotency-Key' header was missing.".to_string())?
+        .to_str()
+        .map_err(|_| {
+            "The 'Idempotency-Key' header was not a valid string.".to_string()
+        })?
+        .to_owned()
+        .try_into()
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    list_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        insert into newsletter_issues (
+            newsletter_issue_id,
+            list_id,
+            title,
+            text_content,
+            html_content,
+            published_at
+        )
+        values ($1, $2, $3, $4, $5, now())
+        "#,
+        newsletter_issue_id,
+        list_id,
+        title,
+        text_content,
+        html_content
+    );
+    transaction.execute(query).await?;
+    Ok(newsletter_issue_id)
+}
 
-    Ok(confirmed_subscribers)
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        insert into issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        select i.newsletter_issue_id, s.email
+        from newsletter_issues i
+        join list_subscriptions l on l.list_id = i.list_id
+        join subscriptions s on s.id = l.subscriber_id
+        where
+            i.newsletter_issue_id = $1 and
+            l.status = 'confirmed' and
+            s.status = 'confirmed' and
+            (s.paused_until is null or s.paused_until <= now())
+        "#,
+        newsletter_issue_id,
+    );
+    transaction.execute(query).await?;
+    Ok(())
 }
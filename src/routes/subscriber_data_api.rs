@@ -0,0 +1,123 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError, http::StatusCode, web,
+};
+use sqlx::PgPool;
+
+use crate::{
+    api_error::{
+        FieldError, describe_field_errors, error_response,
+        internal_error_response, unauthorized_response,
+    },
+    authentication::{AuthError, authenticate_basic},
+    domain::SubscriberEmail,
+    routes::error_chain_fmt,
+    startup::HmacSecret,
+    subscriber_data::{erase_subscriber_data, export_subscriber_data},
+};
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberDataRequest {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataApiError {
+    #[error("{}", describe_field_errors(.0))]
+    ValidationError(Vec<FieldError>),
+    #[error("No data is held about this address.")]
+    NotFound,
+    #[error("Authentication failed.")]
+    AuthError(#[from] AuthError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataApiError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscriberDataApiError::ValidationError(details) => error_response(
+                StatusCode::BAD_REQUEST,
+                "validation_error",
+                "The submitted data is invalid.",
+                details,
+            ),
+            SubscriberDataApiError::NotFound => error_response(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                &self.to_string(),
+                &[],
+            ),
+            SubscriberDataApiError::UnexpectedError(_) => {
+                internal_error_response()
+            }
+            SubscriberDataApiError::AuthError(
+                AuthError::InvalidCredentials(_),
+            ) => unauthorized_response("admin"),
+            SubscriberDataApiError::AuthError(AuthError::UnexpectedError(
+                _,
+            )) => internal_error_response(),
+        }
+    }
+}
+
+/// `POST /api/v1/admin/subscriber_data/export`: everything held about the
+/// address in the JSON body, for answering access requests that reach us by
+/// other means.
+///
+/// The address is never part of the URL, so it stays out of access logs.
+#[tracing::instrument(
+    name = "Export subscriber data for an admin",
+    skip(body, pool, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_subscriber_data_for_admin(
+    body: web::Json<SubscriberDataRequest>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscriberDataApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    let email = parse_email(body.into_inner())?;
+
+    let export = export_subscriber_data(&pool, &email)
+        .await?
+        .ok_or(SubscriberDataApiError::NotFound)?;
+    Ok(HttpResponse::Ok().json(export))
+}
+
+/// `POST /api/v1/admin/subscriber_data/erase`: erase everything held about
+/// the address in the JSON body and keep it from being signed up again.
+///
+/// Addresses we hold nothing about are suppressed all the same.
+#[tracing::instrument(
+    name = "Erase subscriber data for an admin",
+    skip(body, pool, hmac_secret, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn erase_subscriber_data_for_admin(
+    body: web::Json<SubscriberDataRequest>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, SubscriberDataApiError> {
+    authenticate_basic(request.headers(), &pool).await?;
+    let email = parse_email(body.into_inner())?;
+
+    erase_subscriber_data(&pool, &email, &hmac_secret.0).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn parse_email(
+    body: SubscriberDataRequest,
+) -> Result<SubscriberEmail, SubscriberDataApiError> {
+    SubscriberEmail::parse(body.email).map_err(|e| {
+        SubscriberDataApiError::ValidationError(vec![FieldError::new(
+            "email", e,
+        )])
+    })
+}
@@ -1,39 +1,98 @@
 use std::{error::Error, fmt::Display};
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
-    startup::ApplicationBaseUrl,
+    api_error::{
+        FieldError, describe_field_errors, error_response,
+        internal_error_response,
+    },
+    domain::{
+        NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionToken,
+    },
+    email_client::{EmailError, EmailSender},
+    email_policy::EmailPolicy,
+    mailing_list::{MailingList, get_list},
+    signup_protection::{
+        BotCheckFields, SignupCheckError, SignupProtection, Verdict,
+    },
+    startup::{ApplicationBaseUrl, HmacSecret},
+    subscriber_data::is_suppressed,
 };
 use actix_web::{HttpResponse, ResponseError, web};
 use anyhow::Context;
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
+use secrecy::SecretString;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+/// How long a confirmation link stays valid after it was sent.
+pub const CONFIRMATION_TOKEN_TTL: TimeDelta = TimeDelta::hours(24);
+
+/// Minimum time between two confirmation emails to the same subscriber.
+pub const CONFIRMATION_EMAIL_COOLDOWN: TimeDelta = TimeDelta::minutes(5);
+
+/// The `{list}` segment of list-scoped URLs.
+#[derive(serde::Deserialize)]
+pub struct ListPath {
+    pub list: String,
+}
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    /// Set to subscribe the address as typed even though it looks like a
+    /// typo of a common domain.
+    #[serde(default)]
+    pub keep_email: bool,
+    #[serde(flatten)]
+    pub bot_check: BotCheckFields,
 }
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
 
+    /// Validate every field, so that clients learn about all their mistakes
+    /// at once.
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
-        Ok(NewSubscriber { email, name })
+        match (
+            SubscriberName::parse(form.name),
+            SubscriberEmail::parse(form.email),
+        ) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber { email, name }),
+            (name, email) => Err(name
+                .err()
+                .map(|e| FieldError::new("name", e))
+                .into_iter()
+                .chain(email.err().map(|e| FieldError::new("email", e)))
+                .collect()),
+        }
     }
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
+    #[error("{}", describe_field_errors(.0))]
+    ValidationError(Vec<FieldError>),
     #[error("{0}")]
-    ValidationError(String),
+    InvalidProofOfWork(&'static str),
+    #[error("There is no such list.")]
+    UnknownList,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<SignupCheckError> for SubscribeError {
+    fn from(e: SignupCheckError) -> Self {
+        match e {
+            SignupCheckError::InvalidProofOfWork(message) => {
+                SubscribeError::InvalidProofOfWork(message)
+            }
+            SignupCheckError::UnexpectedError(e) => {
+                SubscribeError::UnexpectedError(e)
+            }
+        }
+    }
+}
+
 impl std::fmt::Debug for SubscribeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -42,47 +101,231 @@ impl std::fmt::Debug for SubscribeError {
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => {
+            SubscribeError::ValidationError(_)
+            | SubscribeError::InvalidProofOfWork(_) => {
                 actix_web::http::StatusCode::BAD_REQUEST
             }
+            SubscribeError::UnknownList => {
+                actix_web::http::StatusCode::NOT_FOUND
+            }
             SubscribeError::UnexpectedError(_) => {
                 actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
             }
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::ValidationError(details) => error_response(
+                self.status_code(),
+                "validation_error",
+                "The submitted data is invalid.",
+                details,
+            ),
+            SubscribeError::InvalidProofOfWork(message) => error_response(
+                self.status_code(),
+                "invalid_proof_of_work",
+                message,
+                &[],
+            ),
+            SubscribeError::UnknownList => error_response(
+                self.status_code(),
+                "unknown_list",
+                &self.to_string(),
+                &[],
+            ),
+            SubscribeError::UnexpectedError(_) => internal_error_response(),
+        }
+    }
 }
 
+// Handlers take each piece of application state as an extractor.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name="Adding a new subscriber.",
-    skip(form,pool,email_client,base_url),
+    skip(
+        form,
+        list,
+        pool,
+        email_client,
+        base_url,
+        hmac_secret,
+        email_policy,
+        signup_protection
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name,
     )
 )]
 pub async fn subscribe(
+    list: Option<web::Path<ListPath>>,
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailSender>,
     base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    email_policy: web::Data<EmailPolicy>,
+    signup_protection: web::Data<SignupProtection>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber =
-        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let list = resolve_list(&pool, list.as_deref()).await?;
+    let mut form = form.into_inner();
+    let bot_check = std::mem::take(&mut form.bot_check);
+    let keep_email = form.keep_email;
+    let new_subscriber: NewSubscriber =
+        form.try_into().map_err(SubscribeError::ValidationError)?;
+    check_email_policy(&email_policy, &new_subscriber.email, keep_email)
+        .await?;
+    if signup_protection.check(&bot_check).await? == Verdict::Bot {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    register_subscriber(
+        &pool,
+        email_client.get_ref(),
+        &base_url.0,
+        &hmac_secret.0,
+        &list,
+        &new_subscriber,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The list a list-scoped URL points to, or the default list.
+pub async fn resolve_list(
+    pool: &PgPool,
+    path: Option<&ListPath>,
+) -> Result<MailingList, SubscribeError> {
+    get_list(pool, path.map(|p| p.list.as_str()))
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or(SubscribeError::UnknownList)
+}
 
+/// Refuse addresses we do not want to or cannot deliver to, and ask about
+/// likely typos unless the client said to keep the address as typed.
+pub async fn check_email_policy(
+    email_policy: &EmailPolicy,
+    email: &SubscriberEmail,
+    keep_email: bool,
+) -> Result<(), SubscribeError> {
+    if !keep_email
+        && let Some(suggestion) = email_policy.suggest_correction(email)
+    {
+        return Err(SubscribeError::ValidationError(vec![FieldError::new(
+            "email",
+            format!(
+                "Did you mean {}? Submit the address again with keep_email \
+                set to true to use it as typed.",
+                suggestion
+            ),
+        )]));
+    }
+    email_policy.check(email).await.map_err(|message| {
+        SubscribeError::ValidationError(vec![FieldError::new("email", message)])
+    })
+}
+
+/// Subscribe an address to `list` and send it a confirmation email.
+///
+/// Whatever happens, the outcome must look the same as for a new address, so
+/// that callers cannot reveal who is on the list.
+pub async fn register_subscriber(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+    hmac_secret: &SecretString,
+    list: &MailingList,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), SubscribeError> {
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool.")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    if is_suppressed(&mut *transaction, &new_subscriber.email, hmac_secret)
+        .await
+        .context("Failed to check whether the address is suppressed.")?
+    {
+        tracing::info!(
+            "Ignored a signup for an address whose data was erased."
+        );
+        return Ok(());
+    }
+
+    let (subscriber_id, reactivated) =
+        match insert_subscriber(&mut transaction, new_subscriber)
+            .await
+            .context("Failed to insert new subscriber in the database.")?
+        {
+            Some(subscriber_id) => (subscriber_id, false),
+            None => {
+                let existing =
+                    lock_existing_subscriber(&mut transaction, new_subscriber)
+                        .await
+                        .context(
+                            "Failed to look up the existing subscriber.",
+                        )?;
+                match existing.status.as_str() {
+                    "pending_confirmation" | "confirmed" => {
+                        (existing.id, false)
+                    }
+                    "unsubscribed" => {
+                        reactivate_subscriber(
+                            &mut transaction,
+                            existing.id,
+                            new_subscriber,
+                        )
+                        .await
+                        .context("Failed to reactivate the subscriber.")?;
+                        (existing.id, true)
+                    }
+                    // Addresses that bounced or complained must not be
+                    // mailed again.
+                    _ => return Ok(()),
+                }
+            }
+        };
+
+    // A reactivated address has to confirm every list again.
+    let list_status =
+        get_list_subscription_status(&mut transaction, list, subscriber_id)
+            .await
+            .context("Failed to look up the list subscription.")?;
+    match list_status.as_deref() {
+        // Confirmed subscribers need nothing from us.
+        Some("confirmed") if !reactivated => return Ok(()),
+        // Nor do pending ones who were sent a link a moment ago.
+        Some("pending_confirmation")
+            if !reactivated
+                && confirmation_cooldown_remaining(
+                    &mut transaction,
+                    subscriber_id,
+                    list.list_id,
+                )
+                .await
+                .context("Failed to look up the latest confirmation token.")?
+                .is_some() =>
+        {
+            return Ok(());
+        }
+        _ => {}
+    }
+    upsert_pending_list_subscription(&mut transaction, list, subscriber_id)
+        .await
+        .context("Failed to store the list subscription.")?;
+    revoke_subscription_tokens(&mut transaction, subscriber_id, list.list_id)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to revoke the previous confirmation tokens.")?;
 
-    let subscription_token = Uuid::new_v4().to_string();
+    let subscription_token = SubscriptionToken::generate();
 
     insert_subscription_token(
         &mut transaction,
         &subscriber_id,
+        list.list_id,
         &subscription_token,
     )
     .await
@@ -94,47 +337,57 @@ pub async fn subscribe(
 
     send_confirmation_email(
         email_client,
-        new_subscriber,
-        &base_url.0,
+        &new_subscriber.email,
+        base_url,
+        list,
         &subscription_token,
     )
     .await
     .context("Failed to send subscription confirmation email.")?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
 }
 
-async fn send_confirmation_email(
-    email_client: web::Data<EmailClient>,
-    new_subscriber: NewSubscriber,
+pub async fn send_confirmation_email(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
     base_url: &str,
-    subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    list: &MailingList,
+    subscription_token: &SubscriptionToken,
+) -> Result<(), EmailError> {
     let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
+        "{}{}/subscriptions/confirm?subscription_token={}",
+        base_url,
+        list.path_prefix(),
+        subscription_token.as_ref()
     );
 
     let text_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
+        "{}\nVisit {} to confirm your subscription.",
+        list.confirmation_message, confirmation_link
     );
 
     let html_body = format!(
-        "Welcome to our newsletter!\nVisit <a href=\"{}\">{}</a> to confirm your subscription.",
-        confirmation_link, confirmation_link
+        "{}\nVisit <a href=\"{}\">{}</a> to confirm your subscription.",
+        htmlescape::encode_minimal(&list.confirmation_message),
+        confirmation_link,
+        confirmation_link
     );
 
     email_client
         .send_email(
-            &new_subscriber.email,
-            "Email Confirmation",
+            recipient,
+            &list.confirmation_subject,
             &html_body,
             &text_body,
+            None,
+            list.sender().as_ref(),
         )
-        .await
+        .await?;
+    Ok(())
 }
 
+/// Returns `None` if the email address is already on file.
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -142,21 +395,155 @@ async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    let query = sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (
+            id, email, email_normalized, name, subscribed_at, status
+        )
+        VALUES ($1, $2, $3, $4, $5, 'pending_confirmation')
+        ON CONFLICT (email_normalized) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
+        new_subscriber.email.normalized(),
         new_subscriber.name.as_ref(),
         Utc::now()
-    );
-    transaction.execute(query).await?;
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
 
-    Ok(subscriber_id)
+    Ok(row.map(|r| r.id))
+}
+
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+/// The row lock keeps concurrent requests for the same address from both
+/// issuing a confirmation email.
+#[tracing::instrument(skip_all)]
+async fn lock_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        "select id, status from subscriptions where email_normalized = $1 for update",
+        new_subscriber.email.normalized()
+    )
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+#[tracing::instrument(skip(transaction, list))]
+async fn get_list_subscription_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list: &MailingList,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select status from list_subscriptions
+        where list_id = $1 and subscriber_id = $2
+        "#,
+        list.list_id,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.status))
+}
+
+#[tracing::instrument(skip(transaction, list))]
+async fn upsert_pending_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    list: &MailingList,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into list_subscriptions (
+            list_id, subscriber_id, status, subscribed_at
+        )
+        values ($1, $2, 'pending_confirmation', now())
+        on conflict (list_id, subscriber_id) do update
+        set status = 'pending_confirmation', subscribed_at = now()
+        "#,
+        list.list_id,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Put an unsubscribed address back through double opt-in.
+#[tracing::instrument(skip(transaction, new_subscriber))]
+async fn reactivate_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        update subscriptions
+        set status = 'pending_confirmation', name = $2, subscribed_at = $3
+        where id = $1
+        "#,
+        subscriber_id,
+        new_subscriber.name.as_ref(),
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// How much longer the subscriber has to wait before we send them another
+/// confirmation email for the list, if at all.
+#[tracing::instrument(skip(transaction))]
+pub async fn confirmation_cooldown_remaining(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<Option<TimeDelta>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select max(created_at) as last_created_at
+        from subscription_tokens
+        where subscriber_id = $1 and list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok(row
+        .last_created_at
+        .map(|last| last + CONFIRMATION_EMAIL_COOLDOWN - Utc::now())
+        .filter(|remaining| *remaining > TimeDelta::zero()))
+}
+
+#[tracing::instrument(skip(transaction))]
+pub async fn revoke_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        delete from subscription_tokens
+        where subscriber_id = $1 and list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -181,7 +568,11 @@ impl Error for StoreTokenError {
     }
 }
 
-impl ResponseError for StoreTokenError {}
+impl ResponseError for StoreTokenError {
+    fn error_response(&self) -> HttpResponse {
+        internal_error_response()
+    }
+}
 
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
@@ -196,22 +587,30 @@ pub fn error_chain_fmt(
     Ok(())
 }
 #[tracing::instrument(
-    name = "Saving new subscriber details in the database",
+    name = "Storing a subscription token in the database",
     skip(subscriber_id, subscription_token, transaction)
 )]
 pub async fn insert_subscription_token(
     transaction: &mut Transaction<'static, Postgres>,
     subscriber_id: &Uuid,
-    subscription_token: &str,
+    list_id: Uuid,
+    subscription_token: &SubscriptionToken,
 ) -> Result<(), StoreTokenError> {
+    let now = Utc::now();
     let query = sqlx::query(
         r#"
-        INSERT INTO subscription_tokens (subscriber_id, subscription_token )
-        VALUES ($1, $2);
+        INSERT INTO subscription_tokens (
+            subscriber_id, list_id, subscription_token_hash, created_at,
+            expires_at
+        )
+        VALUES ($1, $2, $3, $4, $5);
         "#,
     )
     .bind(subscriber_id)
-    .bind(subscription_token);
+    .bind(list_id)
+    .bind(subscription_token.hash())
+    .bind(now)
+    .bind(now + CONFIRMATION_TOKEN_TTL);
     transaction.execute(query).await.map_err(StoreTokenError)?;
     Ok(())
 }
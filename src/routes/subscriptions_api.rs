@@ -0,0 +1,102 @@
+use actix_web::{Either, HttpResponse, web};
+use sqlx::PgPool;
+
+use crate::{
+    domain::NewSubscriber,
+    email_client::EmailSender,
+    email_policy::EmailPolicy,
+    routes::{
+        FormData, ListPath, SubscribeError, check_email_policy,
+        register_subscriber, resolve_list,
+    },
+    signup_protection::{SignupProtection, Verdict},
+    startup::{ApplicationBaseUrl, HmacSecret},
+};
+
+/// The pending subscription, as returned to API clients.
+///
+/// It is built from the request alone: existing subscribers get exactly the
+/// same answer as new ones.
+#[derive(serde::Serialize)]
+pub struct SubscriptionResource<'a> {
+    pub email: &'a str,
+    pub name: &'a str,
+    pub list: &'a str,
+    pub status: &'static str,
+}
+
+/// `POST /api/v1/subscriptions` (or `/api/v1/lists/{list}/subscriptions`),
+/// for clients that are not HTML forms.
+///
+/// Accepts JSON as well as form-encoded bodies; both go through the same
+/// validation, domain policy and bot checks as the `/subscriptions` form.
+// Handlers take each piece of application state as an extractor.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Adding a new subscriber through the API.",
+    skip(
+        list,
+        body,
+        pool,
+        email_client,
+        base_url,
+        hmac_secret,
+        email_policy,
+        signup_protection
+    ),
+    fields(
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty,
+    )
+)]
+pub async fn create_subscription(
+    list: Option<web::Path<ListPath>>,
+    body: Either<web::Json<FormData>, web::Form<FormData>>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    email_policy: web::Data<EmailPolicy>,
+    signup_protection: web::Data<SignupProtection>,
+) -> Result<HttpResponse, SubscribeError> {
+    let list = resolve_list(&pool, list.as_deref()).await?;
+    let mut form = match body {
+        Either::Left(json) => json.into_inner(),
+        Either::Right(form) => form.into_inner(),
+    };
+    let bot_check = std::mem::take(&mut form.bot_check);
+    let keep_email = form.keep_email;
+    let new_subscriber: NewSubscriber =
+        form.try_into().map_err(SubscribeError::ValidationError)?;
+    tracing::Span::current()
+        .record(
+            "subscriber_email",
+            tracing::field::display(&new_subscriber.email),
+        )
+        .record(
+            "subscriber_name",
+            tracing::field::display(new_subscriber.name.as_ref()),
+        );
+    check_email_policy(&email_policy, &new_subscriber.email, keep_email)
+        .await?;
+
+    if signup_protection.check(&bot_check).await? == Verdict::Human {
+        register_subscriber(
+            &pool,
+            email_client.get_ref(),
+            &base_url.0,
+            &hmac_secret.0,
+            &list,
+            &new_subscriber,
+        )
+        .await?;
+    }
+
+    // The subscription only becomes active once the email is confirmed.
+    Ok(HttpResponse::Accepted().json(SubscriptionResource {
+        email: new_subscriber.email.as_ref(),
+        name: new_subscriber.name.as_ref(),
+        list: &list.slug,
+        status: "pending_confirmation",
+    }))
+}
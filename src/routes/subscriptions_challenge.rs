@@ -0,0 +1,18 @@
+use actix_web::{
+    HttpResponse,
+    http::header::{CacheControl, CacheDirective},
+    web,
+};
+
+use crate::signup_protection::SignupProtection;
+
+/// `GET /subscriptions/challenge`: a fresh proof-of-work challenge for the
+/// signup form.
+#[tracing::instrument(name = "Issuing a proof-of-work challenge.", skip_all)]
+pub async fn issue_challenge(
+    signup_protection: web::Data<SignupProtection>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(signup_protection.issue_challenge())
+}
@@ -1,76 +1,326 @@
-use actix_web::{HttpResponse, web};
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{
+        StatusCode,
+        header::{self, ContentType},
+    },
+    web,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::{
+    api_error::{client_accepts_json, error_response, internal_error_response},
+    domain::SubscriptionToken,
+    mailing_list::get_list,
+    routes::{ListPath, error_chain_fmt},
+    startup::{ConfirmationRedirectUrl, HmacSecret},
+    subscriber_token::preferences_link,
+};
+
 #[derive(Debug, Deserialize)]
 pub struct Parameters {
-    #[allow(dead_code)]
     subscription_token: String,
 }
 
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("The confirmation link is invalid.")]
+    InvalidToken,
+    #[error("The confirmation link has expired.")]
+    ExpiredToken { resend_path: String },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::InvalidToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::ExpiredToken { .. } => StatusCode::GONE,
+            ConfirmError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Browsers following the link get a page; API clients that ask for JSON
+    /// get the usual error envelope.
+    fn error_response(&self) -> HttpResponse {
+        if client_accepts_json() {
+            return match self {
+                ConfirmError::InvalidToken => error_response(
+                    self.status_code(),
+                    "invalid_token",
+                    &self.to_string(),
+                    &[],
+                ),
+                ConfirmError::ExpiredToken { .. } => error_response(
+                    self.status_code(),
+                    "expired_token",
+                    &self.to_string(),
+                    &[],
+                ),
+                ConfirmError::UnexpectedError(_) => internal_error_response(),
+            };
+        }
+        let body = match self {
+            ConfirmError::InvalidToken => confirmation_page(
+                "Invalid confirmation link",
+                "<p>This confirmation link is not valid. Please check that \
+                you copied the whole link from the email.</p>",
+            ),
+            // The link was genuine but is too old to use: offer a new one.
+            ConfirmError::ExpiredToken { resend_path } => confirmation_page(
+                "Confirmation link expired",
+                &format!(
+                    r#"<p>This confirmation link has expired.</p>
+    <p>Enter your email address and we will send you a new one.</p>
+    <form action="{}" method="post">
+        <label>Email
+            <input type="email" name="email" required>
+        </label>
+        <button type="submit">Send a new link</button>
+    </form>"#,
+                    // Built from the list slug, which is URL-safe.
+                    resend_path
+                ),
+            ),
+            ConfirmError::UnexpectedError(_) => confirmation_page(
+                "Something went wrong",
+                "<p>We could not confirm your subscription. Please try again \
+                later.</p>",
+            ),
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(body)
+    }
+}
+
+/// Confirm a subscription to a list. Each token can be used once: following
+/// it again shows that the subscription is already confirmed and changes
+/// nothing.
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(parameters, pool,)
+    skip(list, parameters, pool, redirect_url, hmac_secret)
 )]
 pub async fn confirm(
+    list: Option<web::Path<ListPath>>,
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
-) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(
-        &pool,
-        &parameters.subscription_token,
-    )
-    .await
-    {
-        Ok(id) => id,
-        Err(_) => {
-            return HttpResponse::InternalServerError().finish();
-        }
-    };
+    redirect_url: web::Data<ConfirmationRedirectUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, ConfirmError> {
+    let subscription_token =
+        SubscriptionToken::from_link(parameters.0.subscription_token);
 
-    match id {
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+    let list = get_list(&**pool, list.as_deref().map(|p| p.list.as_str()))
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or(ConfirmError::InvalidToken)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // A token only confirms the list it was sent for.
+    let token = lock_token_record(&mut transaction, &subscription_token)
+        .await
+        .context("Failed to look up the confirmation token.")?
+        .filter(|t| t.list_id == list.list_id)
+        .ok_or(ConfirmError::InvalidToken)?;
+
+    let already_confirmed = token.consumed_at.is_some()
+        || token.list_status.as_deref() == Some("confirmed");
+    if !already_confirmed {
+        if token.expires_at <= Utc::now() {
+            return Err(ConfirmError::ExpiredToken {
+                resend_path: format!(
+                    "{}/subscriptions/resend",
+                    list.path_prefix()
+                ),
+            });
+        }
+        // Addresses that bounced, complained or unsubscribed since the link
+        // was sent have to start over.
+        if !["pending_confirmation", "confirmed"]
+            .contains(&token.status.as_str())
+            || token.list_status.as_deref() != Some("pending_confirmation")
+        {
+            return Err(ConfirmError::InvalidToken);
         }
-        None => HttpResponse::Unauthorized().finish(),
+        confirm_subscriber(&mut transaction, token.subscriber_id)
+            .await
+            .context("Failed to mark the subscriber as confirmed.")?;
+        confirm_list_subscription(
+            &mut transaction,
+            token.list_id,
+            token.subscriber_id,
+        )
+        .await
+        .context("Failed to mark the list subscription as confirmed.")?;
+        consume_token(&mut transaction, &token.subscription_token_hash)
+            .await
+            .context("Failed to consume the confirmation token.")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the SQL transaction to confirm.")?;
     }
+
+    if let Some(redirect_url) = &redirect_url.0 {
+        return Ok(HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, redirect_url.as_str()))
+            .finish());
+    }
+    let preferences = format!(
+        r#"<p>You can <a href="{}">change your preferences</a> at any time.</p>"#,
+        htmlescape::encode_attribute(&preferences_link(
+            "",
+            token.subscriber_id,
+            &hmac_secret.0
+        ))
+    );
+    let body = if already_confirmed {
+        confirmation_page(
+            "Already confirmed",
+            &format!(
+                "<p>Your subscription is already confirmed. There is nothing \
+                else to do.</p>\n    {}",
+                preferences
+            ),
+        )
+    } else {
+        confirmation_page(
+            "Subscription confirmed",
+            &format!(
+                "<p>Thanks for confirming! You will receive our next \
+                issue.</p>\n    {}",
+                preferences
+            ),
+        )
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// The layout shared by every page of the confirmation flow. `content` is
+/// inserted as is and must already be escaped.
+fn confirmation_page(title: &str, content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    {content}
+</body>
+</html>"#,
+        title = htmlescape::encode_minimal(title),
+    )
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update subscriptions set status = 'confirmed'
+        where id = $1 and status = 'pending_confirmation'"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
-pub async fn confirm_subscriber(
-    pool: &PgPool,
+#[tracing::instrument(skip(transaction))]
+async fn confirm_list_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
     subscriber_id: Uuid,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        r#"update subscriptions set status = 'confirmed' where id = $1;"#,
+    sqlx::query!(
+        r#"update list_subscriptions set status = 'confirmed'
+        where list_id = $1 and subscriber_id = $2"#,
+        list_id,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"update subscription_tokens set consumed_at = now()
+        where subscription_token_hash = $1"#,
+        subscription_token_hash
     )
-    .bind(subscriber_id)
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .execute(&mut **transaction)
+    .await?;
     Ok(())
 }
 
-async fn get_subscriber_id_from_token(
-    pool: &PgPool,
-    subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "select subscriber_id, subscription_token from subscription_tokens \
-        where subscription_token = $1",
-        subscription_token
+struct TokenRecord {
+    subscription_token_hash: String,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    status: String,
+    list_status: Option<String>,
+}
+
+/// Locking the token serialises concurrent clicks on the same link.
+#[tracing::instrument(skip_all)]
+async fn lock_token_record(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &SubscriptionToken,
+) -> Result<Option<TokenRecord>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        TokenRecord,
+        r#"
+        select
+            t.subscription_token_hash,
+            t.subscriber_id,
+            t.list_id,
+            t.expires_at,
+            t.consumed_at,
+            s.status,
+            l.status as "list_status?"
+        from subscription_tokens t
+        join subscriptions s on s.id = t.subscriber_id
+        left join list_subscriptions l
+            on l.list_id = t.list_id and l.subscriber_id = t.subscriber_id
+        where t.subscription_token_hash = $1
+        for update of t
+        "#,
+        subscription_token.hash()
     )
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
+    .fetch_optional(&mut **transaction)
+    .await?;
+    // The index lookup is on a digest, so its timing says nothing useful about
+    // the token; the final comparison is still done in constant time.
+    Ok(result
+        .filter(|r| subscription_token.matches(&r.subscription_token_hash)))
 }
@@ -0,0 +1,330 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{
+        StatusCode,
+        header::{ContentDisposition, ContentType},
+    },
+    web,
+};
+use anyhow::Context;
+use chrono::{TimeDelta, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    api_error::{
+        FieldError, describe_field_errors, error_response,
+        internal_error_response,
+    },
+    domain::SubscriberEmail,
+    email_client::EmailSender,
+    routes::error_chain_fmt,
+    startup::{ApplicationBaseUrl, HmacSecret},
+    subscriber_data::{
+        erase_subscriber_data, export_subscriber_data, get_subscriber_email,
+    },
+    subscriber_token::{
+        SubscriberToken, TokenPurpose, data_erasure_link, data_export_link,
+    },
+};
+
+const DATA_LINK_TTL: TimeDelta = TimeDelta::hours(24);
+
+#[derive(serde::Deserialize)]
+pub struct DataRequestFormData {
+    email: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SubscriberDataParameters {
+    subscriber_id: Uuid,
+    /// When the link was sent, as a Unix timestamp.
+    issued_at: i64,
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberDataError {
+    #[error("{}", describe_field_errors(.0))]
+    ValidationError(Vec<FieldError>),
+    #[error("The link is invalid.")]
+    InvalidToken,
+    #[error("The link has expired. Ask for a new one.")]
+    ExpiredToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscriberDataError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscriberDataError::InvalidToken
+            | SubscriberDataError::ExpiredToken => StatusCode::UNAUTHORIZED,
+            SubscriberDataError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscriberDataError::ValidationError(details) => error_response(
+                self.status_code(),
+                "validation_error",
+                "The submitted data is invalid.",
+                details,
+            ),
+            SubscriberDataError::InvalidToken => error_response(
+                self.status_code(),
+                "invalid_token",
+                &self.to_string(),
+                &[],
+            ),
+            SubscriberDataError::ExpiredToken => error_response(
+                self.status_code(),
+                "expired_token",
+                &self.to_string(),
+                &[],
+            ),
+            SubscriberDataError::UnexpectedError(_) => {
+                internal_error_response()
+            }
+        }
+    }
+}
+
+/// Email the owner of an address the links to download or erase their data.
+///
+/// Only whoever can read the mailbox gets to see the data. Known and unknown
+/// addresses get the same page and status code, even when the email cannot be
+/// sent: a failed send is only logged. Response times still differ, and the
+/// rate limiter in front of this route is what limits probing.
+#[tracing::instrument(
+    name = "Send the subscriber data links",
+    skip(form, pool, email_client, base_url, hmac_secret),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn request_subscriber_data(
+    form: web::Form<DataRequestFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(|e| {
+        SubscriberDataError::ValidationError(vec![FieldError::new("email", e)])
+    })?;
+
+    if let Some(subscriber_id) = get_subscriber_id(&pool, &email)
+        .await
+        .context("Failed to look up the subscriber.")?
+    {
+        let issued_at = Utc::now().timestamp();
+        let export_link = data_export_link(
+            &base_url.0,
+            subscriber_id,
+            issued_at,
+            &hmac_secret.0,
+        );
+        let erasure_link = data_erasure_link(
+            &base_url.0,
+            subscriber_id,
+            issued_at,
+            &hmac_secret.0,
+        );
+        if let Err(e) = send_data_links_email(
+            email_client.get_ref(),
+            &email,
+            &export_link,
+            &erasure_link,
+        )
+        .await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send the subscriber data links."
+            );
+        }
+    }
+
+    Ok(data_page(
+        "Check your inbox",
+        "<p>If we hold any data about that address, a link to it is on its \
+        way.</p>",
+    ))
+}
+
+async fn send_data_links_email(
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    export_link: &str,
+    erasure_link: &str,
+) -> Result<(), anyhow::Error> {
+    let text_body = format!(
+        "You asked about the data we hold about you.\n\
+        Download a copy of it: {}\n\
+        Erase it for good: {}\n\
+        If you did not ask for this, you can ignore this email.",
+        export_link, erasure_link
+    );
+    let html_body = format!(
+        "<p>You asked about the data we hold about you.</p>\
+        <p><a href=\"{}\">Download a copy of it</a></p>\
+        <p><a href=\"{}\">Erase it for good</a></p>\
+        <p>If you did not ask for this, you can ignore this email.</p>",
+        htmlescape::encode_attribute(export_link),
+        htmlescape::encode_attribute(erasure_link)
+    );
+    email_client
+        .send_email(recipient, "Your data", &html_body, &text_body, None, None)
+        .await?;
+    Ok(())
+}
+
+/// Everything we hold about the subscriber, as a JSON download.
+#[tracing::instrument(
+    name = "Export a subscriber's data",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn export_data(
+    parameters: web::Query<SubscriberDataParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    verify_token(&parameters, TokenPurpose::ExportData, &hmac_secret)?;
+    // Links outlive the data they point to.
+    let email = get_subscriber_email(&pool, parameters.subscriber_id)
+        .await?
+        .ok_or(SubscriberDataError::InvalidToken)?;
+    let export = export_subscriber_data(&pool, &email)
+        .await?
+        .ok_or(SubscriberDataError::InvalidToken)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition::attachment("subscriber-data.json"))
+        .insert_header(("Cache-Control", "no-store"))
+        .json(export))
+}
+
+/// Ask for confirmation first: link scanners and mail previewers follow
+/// `GET` links, so only `POST` may erase anything.
+#[tracing::instrument(
+    name = "Show the data erasure page",
+    skip(parameters, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn erase_data_form(
+    parameters: web::Query<SubscriberDataParameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    verify_token(&parameters, TokenPurpose::EraseData, &hmac_secret)?;
+
+    Ok(data_page(
+        "Erase your data",
+        &format!(
+            r#"<p>
+        Do you want us to erase everything we hold about you? You will stop
+        receiving our newsletter and this address will not be able to
+        subscribe again.
+    </p>
+    <form action="{}" method="post">
+        <button type="submit">Erase my data</button>
+    </form>"#,
+            htmlescape::encode_attribute(&data_erasure_link(
+                "",
+                parameters.subscriber_id,
+                parameters.issued_at,
+                &hmac_secret.0
+            ))
+        ),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Erase a subscriber's data",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn erase_data(
+    parameters: web::Query<SubscriberDataParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, SubscriberDataError> {
+    verify_token(&parameters, TokenPurpose::EraseData, &hmac_secret)?;
+
+    // Erasing twice is not an error.
+    if let Some(email) =
+        get_subscriber_email(&pool, parameters.subscriber_id).await?
+    {
+        erase_subscriber_data(&pool, &email, &hmac_secret.0).await?;
+    }
+
+    Ok(data_page(
+        "Data erased",
+        "<p>Your data has been erased. You will not hear from us again.</p>",
+    ))
+}
+
+/// Data links hand over or destroy everything we hold: they only work for a
+/// day, in case an old email is forwarded or leaked.
+fn verify_token(
+    parameters: &SubscriberDataParameters,
+    purpose: TokenPurpose,
+    hmac_secret: &HmacSecret,
+) -> Result<(), SubscriberDataError> {
+    if !SubscriberToken::verify_issued_at(
+        &parameters.token,
+        purpose,
+        parameters.subscriber_id,
+        parameters.issued_at,
+        &hmac_secret.0,
+    ) {
+        return Err(SubscriberDataError::InvalidToken);
+    }
+    let age = Utc::now().timestamp().saturating_sub(parameters.issued_at);
+    if !(0..=DATA_LINK_TTL.num_seconds()).contains(&age) {
+        return Err(SubscriberDataError::ExpiredToken);
+    }
+    Ok(())
+}
+
+/// `content` is inserted as is and must already be escaped.
+fn data_page(title: &str, content: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {content}
+</body>
+</html>"#
+        ))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscriber_id(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        "select id from subscriptions where email_normalized = $1",
+        email.normalized()
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
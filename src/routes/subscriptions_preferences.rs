@@ -0,0 +1,506 @@
+use std::{collections::HashMap, fmt::Write};
+
+use actix_web::{
+    HttpResponse, ResponseError, http::StatusCode, http::header::ContentType,
+    web,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_aux::field_attributes::deserialize_option_number_from_string;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    api_error::{error_response, internal_error_response},
+    domain::{EmailFormat, SubscriberName},
+    mailing_list::get_all_lists,
+    routes::{error_chain_fmt, unsubscribe_if_on_no_list},
+    startup::HmacSecret,
+    subscriber_token::{
+        SubscriberToken, TokenPurpose, data_erasure_link, data_export_link,
+        unsubscribe_link,
+    },
+    utils::see_other,
+};
+
+/// The longest a subscriber can pause delivery for in one go.
+const MAX_PAUSE_DAYS: u32 = 365;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PreferencesParameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+impl PreferencesParameters {
+    fn page_path(&self) -> String {
+        format!(
+            "/subscriptions/preferences?subscriber_id={}&token={}",
+            self.subscriber_id, self.token
+        )
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesFormData {
+    name: String,
+    email_format: EmailFormat,
+    /// Left empty to keep the current pause, `0` to resume delivery.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_option_number_from_string"
+    )]
+    pause_days: Option<u32>,
+    /// The slugs of the lists the form offered, comma separated. Lists that
+    /// were not offered are left alone.
+    #[serde(default)]
+    shown_lists: String,
+    /// A `list.{slug}` entry for every ticked list.
+    #[serde(flatten)]
+    ticked_lists: HashMap<String, String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The preferences link is invalid.")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken => StatusCode::UNAUTHORIZED,
+            PreferencesError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PreferencesError::InvalidToken => error_response(
+                self.status_code(),
+                "invalid_token",
+                &self.to_string(),
+                &[],
+            ),
+            PreferencesError::UnexpectedError(_) => internal_error_response(),
+        }
+    }
+}
+
+struct Preferences {
+    email: String,
+    name: String,
+    status: String,
+    email_format: String,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+struct ListChoice {
+    slug: String,
+    name: String,
+    status: Option<String>,
+}
+
+/// The preference center, reached through a signed link: no account needed.
+#[tracing::instrument(
+    name = "Show the preferences page",
+    skip(parameters, pool, hmac_secret, flash_messages),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn preferences_form(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PreferencesError> {
+    verify_token(&parameters, &hmac_secret)?;
+    let preferences = get_preferences(&pool, parameters.subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber's preferences.")?
+        .ok_or(PreferencesError::InvalidToken)?;
+
+    let mut messages_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            messages_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    if !["pending_confirmation", "confirmed"]
+        .contains(&preferences.status.as_str())
+    {
+        return Ok(preferences_page(&format!(
+            "{}<p>{} is not subscribed to our newsletter any more.</p>",
+            messages_html,
+            htmlescape::encode_minimal(&preferences.email)
+        )));
+    }
+
+    let checked = |format: EmailFormat| {
+        if preferences.email_format == format.as_str() {
+            " checked"
+        } else {
+            ""
+        }
+    };
+    let pause_options = match preferences.paused_until {
+        Some(paused_until) if paused_until > Utc::now() => format!(
+            r#"<option value="" selected>Stay paused until {}</option>
+            <option value="0">Resume now</option>"#,
+            paused_until.format("%Y-%m-%d")
+        ),
+        _ => r#"<option value="" selected>Keep receiving issues</option>"#
+            .to_owned(),
+    };
+    let unsubscribe_path =
+        unsubscribe_link("", parameters.subscriber_id, &hmac_secret.0);
+    // Data links expire: they are issued afresh each time the page is shown.
+    let issued_at = Utc::now().timestamp();
+    let lists = get_list_choices(&pool, parameters.subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber's lists.")?;
+    let mut lists_html = String::new();
+    for list in &lists {
+        let (checked, note) = match list.status.as_deref() {
+            Some("confirmed") => (" checked", ""),
+            Some("pending_confirmation") => {
+                (" checked", " (waiting for confirmation)")
+            }
+            _ => ("", ""),
+        };
+        write!(
+            lists_html,
+            r#"
+            <label>
+                <input type="checkbox" name="list.{}" value="on"{}>
+                {}{}
+            </label>"#,
+            htmlescape::encode_attribute(&list.slug),
+            checked,
+            htmlescape::encode_minimal(&list.name),
+            note
+        )
+        .unwrap();
+    }
+    let shown_lists = lists
+        .iter()
+        .map(|l| l.slug.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Ok(preferences_page(&format!(
+        r#"{messages_html}
+    <p>Issues are sent to {email}.</p>
+    <form action="{page_path}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{name}" required>
+        </label>
+        <fieldset>
+            <legend>Lists</legend>
+            <input type="hidden" name="shown_lists" value="{shown_lists}">{lists_html}
+        </fieldset>
+        <fieldset>
+            <legend>Format</legend>
+            <label>
+                <input type="radio" name="email_format" value="html"{html}>
+                HTML
+            </label>
+            <label>
+                <input type="radio" name="email_format" value="text"{text}>
+                Plain text
+            </label>
+        </fieldset>
+        <label>Pause delivery
+            <select name="pause_days">
+            {pause_options}
+            <option value="7">For a week</option>
+            <option value="30">For a month</option>
+            <option value="90">For three months</option>
+            </select>
+        </label>
+        <button type="submit">Save</button>
+    </form>
+    <form action="{unsubscribe_path}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+    <h2>Your data</h2>
+    <p>
+        <a href="{export_path}">Download a copy of your data</a> or
+        <a href="{erasure_path}">erase it</a>.
+    </p>"#,
+        email = htmlescape::encode_minimal(&preferences.email),
+        page_path = htmlescape::encode_attribute(&parameters.page_path()),
+        name = htmlescape::encode_attribute(&preferences.name),
+        shown_lists = htmlescape::encode_attribute(&shown_lists),
+        html = checked(EmailFormat::Html),
+        text = checked(EmailFormat::Text),
+        unsubscribe_path = htmlescape::encode_attribute(&unsubscribe_path),
+        export_path = htmlescape::encode_attribute(&data_export_link(
+            "",
+            parameters.subscriber_id,
+            issued_at,
+            &hmac_secret.0
+        )),
+        erasure_path = htmlescape::encode_attribute(&data_erasure_link(
+            "",
+            parameters.subscriber_id,
+            issued_at,
+            &hmac_secret.0
+        )),
+    )))
+}
+
+#[tracing::instrument(
+    name = "Update a subscriber's preferences",
+    skip(parameters, form, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn update_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    form: web::Form<PreferencesFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    verify_token(&parameters, &hmac_secret)?;
+    let form = form.into_inner();
+
+    let name = match SubscriberName::parse(form.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&parameters.page_path()));
+        }
+    };
+    let pause = match form.pause_days {
+        Some(days) if days > MAX_PAUSE_DAYS => {
+            FlashMessage::error(format!(
+                "Delivery can be paused for at most {} days.",
+                MAX_PAUSE_DAYS
+            ))
+            .send();
+            return Ok(see_other(&parameters.page_path()));
+        }
+        Some(0) => Pause::Resume,
+        Some(days) => {
+            Pause::Until(Utc::now() + TimeDelta::days(i64::from(days)))
+        }
+        None => Pause::Keep,
+    };
+
+    let lists = form
+        .shown_lists
+        .split(',')
+        .filter(|slug| !slug.is_empty())
+        .map(|slug| {
+            let ticked =
+                form.ticked_lists.contains_key(&format!("list.{slug}"));
+            (slug, ticked)
+        })
+        .collect::<Vec<_>>();
+
+    store_preferences(
+        &pool,
+        parameters.subscriber_id,
+        &name,
+        form.email_format,
+        pause,
+        &lists,
+    )
+    .await
+    .context("Failed to store the subscriber's preferences.")?;
+
+    FlashMessage::info("Your preferences have been saved.").send();
+    Ok(see_other(&parameters.page_path()))
+}
+
+fn verify_token(
+    parameters: &PreferencesParameters,
+    hmac_secret: &HmacSecret,
+) -> Result<(), PreferencesError> {
+    if SubscriberToken::verify(
+        &parameters.token,
+        TokenPurpose::ManagePreferences,
+        parameters.subscriber_id,
+        &hmac_secret.0,
+    ) {
+        Ok(())
+    } else {
+        Err(PreferencesError::InvalidToken)
+    }
+}
+
+/// `content` is inserted as is and must already be escaped.
+fn preferences_page(content: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your preferences</title>
+</head>
+<body>
+    <h1>Your preferences</h1>
+    {content}
+</body>
+</html>"#
+        ))
+}
+
+enum Pause {
+    Keep,
+    Resume,
+    Until(DateTime<Utc>),
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<Preferences>, sqlx::Error> {
+    sqlx::query_as!(
+        Preferences,
+        r#"
+        select email, name, status, email_format, paused_until
+        from subscriptions
+        where id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Every list, with the subscriber's membership of it.
+#[tracing::instrument(skip(pool))]
+async fn get_list_choices(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListChoice>, anyhow::Error> {
+    let lists = get_all_lists(pool).await?;
+    let memberships = sqlx::query!(
+        r#"
+        select list_id, status from list_subscriptions
+        where subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(lists
+        .into_iter()
+        .map(|list| ListChoice {
+            status: memberships
+                .iter()
+                .find(|m| m.list_id == list.list_id)
+                .map(|m| m.status.clone()),
+            slug: list.slug,
+            name: list.name,
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip(pool, name, pause, lists))]
+async fn store_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    email_format: EmailFormat,
+    pause: Pause,
+    lists: &[(&str, bool)],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let (keep_pause, paused_until) = match pause {
+        Pause::Keep => (true, None),
+        Pause::Resume => (false, None),
+        Pause::Until(until) => (false, Some(until)),
+    };
+    sqlx::query!(
+        r#"
+        update subscriptions
+        set
+            name = $2,
+            email_format = $3,
+            paused_until = case when $4 then paused_until else $5 end
+        where
+            id = $1 and
+            status in ('pending_confirmation', 'confirmed')
+        "#,
+        subscriber_id,
+        name.as_ref(),
+        email_format.as_str(),
+        keep_pause,
+        paused_until
+    )
+    .execute(&mut *transaction)
+    .await?;
+    for (slug, ticked) in lists {
+        store_list_choice(&mut transaction, subscriber_id, slug, *ticked)
+            .await?;
+    }
+    if !lists.is_empty() {
+        unsubscribe_if_on_no_list(&mut transaction, subscriber_id).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Join or leave a list. The address has already been confirmed, so joining
+/// takes effect at once; addresses still waiting for their first
+/// confirmation cannot join further lists from here.
+#[tracing::instrument(skip(transaction))]
+async fn store_list_choice(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    slug: &str,
+    ticked: bool,
+) -> Result<(), sqlx::Error> {
+    if ticked {
+        sqlx::query!(
+            r#"
+            insert into list_subscriptions (
+                list_id, subscriber_id, status, subscribed_at
+            )
+            select l.list_id, s.id, 'confirmed', now()
+            from lists l, subscriptions s
+            where l.slug = $2 and s.id = $1 and s.status = 'confirmed'
+            on conflict (list_id, subscriber_id) do update
+            set status = 'confirmed', subscribed_at = now()
+            where list_subscriptions.status = 'unsubscribed'
+            "#,
+            subscriber_id,
+            slug
+        )
+        .execute(&mut **transaction)
+        .await?;
+    } else {
+        sqlx::query!(
+            r#"
+            update list_subscriptions set status = 'unsubscribed'
+            where
+                subscriber_id = $1 and
+                list_id = (select list_id from lists where slug = $2)
+            "#,
+            subscriber_id,
+            slug
+        )
+        .execute(&mut **transaction)
+        .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{StatusCode, header::ContentType},
+    web,
+};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    api_error::{
+        FieldError, describe_field_errors, error_response,
+        internal_error_response,
+    },
+    domain::{SubscriberEmail, SubscriptionToken},
+    email_client::EmailSender,
+    mailing_list::get_list,
+    routes::{
+        ListPath, confirmation_cooldown_remaining, error_chain_fmt,
+        insert_subscription_token, revoke_subscription_tokens,
+        send_confirmation_email,
+    },
+    startup::ApplicationBaseUrl,
+};
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ResendError {
+    #[error("{}", describe_field_errors(.0))]
+    ValidationError(Vec<FieldError>),
+    #[error("There is no such list.")]
+    UnknownList,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ResendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ResendError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ResendError::ValidationError(details) => error_response(
+                StatusCode::BAD_REQUEST,
+                "validation_error",
+                "The submitted data is invalid.",
+                details,
+            ),
+            ResendError::UnknownList => error_response(
+                StatusCode::NOT_FOUND,
+                "unknown_list",
+                &self.to_string(),
+                &[],
+            ),
+            ResendError::UnexpectedError(_) => internal_error_response(),
+        }
+    }
+}
+
+/// Send a fresh confirmation link to a subscriber who has not confirmed yet.
+///
+/// Earlier links are revoked. Unknown, confirmed and recently mailed pending
+/// addresses all get the same page and are simply not sent anything: the
+/// status code does not tell them apart. Visible throttling is left to the
+/// per-IP and per-email rate limiter in front of this route.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(list, form, pool, email_client, base_url),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    list: Option<web::Path<ListPath>>,
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ResendError> {
+    let list = get_list(&**pool, list.as_deref().map(|p| p.list.as_str()))
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or(ResendError::UnknownList)?;
+    let email = SubscriberEmail::parse(form.0.email).map_err(|e| {
+        ResendError::ValidationError(vec![FieldError::new("email", e)])
+    })?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let Some(subscriber_id) =
+        lock_pending_subscriber(&mut transaction, &email, list.list_id)
+            .await
+            .context("Failed to look up the pending subscriber.")?
+    else {
+        return Ok(resent_page());
+    };
+
+    if confirmation_cooldown_remaining(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+    )
+    .await
+    .context("Failed to look up the latest confirmation token.")?
+    .is_some()
+    {
+        return Ok(resent_page());
+    }
+
+    let subscription_token = SubscriptionToken::generate();
+    revoke_subscription_tokens(&mut transaction, subscriber_id, list.list_id)
+        .await
+        .context("Failed to revoke the previous confirmation tokens.")?;
+    insert_subscription_token(
+        &mut transaction,
+        &subscriber_id,
+        list.list_id,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the new confirmation token.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to resend a token.")?;
+
+    send_confirmation_email(
+        email_client.get_ref(),
+        &email,
+        &base_url.0,
+        &list,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to resend the subscription confirmation email.")?;
+
+    Ok(resent_page())
+}
+
+fn resent_page() -> HttpResponse {
+    HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Check your inbox</title>
+</head>
+<body>
+    <p>If that address is waiting for confirmation, a new link is on its way.</p>
+</body>
+</html>"#,
+    )
+}
+
+/// Locking the row serialises concurrent resends for the same subscriber,
+/// which would otherwise both get past the cooldown check.
+#[tracing::instrument(skip_all)]
+async fn lock_pending_subscriber(
+    transaction: &mut Transaction<'static, Postgres>,
+    email: &SubscriberEmail,
+    list_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        select s.id from subscriptions s
+        join list_subscriptions l on l.subscriber_id = s.id
+        where
+            s.email_normalized = $1 and
+            s.status in ('pending_confirmation', 'confirmed') and
+            l.list_id = $2 and
+            l.status = 'pending_confirmation'
+        for update of s
+        "#,
+        email.normalized(),
+        list_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
@@ -0,0 +1,255 @@
+use actix_web::{
+    HttpResponse, ResponseError, http::StatusCode, http::header::ContentType,
+    web,
+};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    api_error::{error_response, internal_error_response},
+    mailing_list::{MailingList, get_list},
+    routes::error_chain_fmt,
+    startup::HmacSecret,
+    subscriber_token::{SubscriberToken, TokenPurpose, preferences_link},
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    token: String,
+    /// Set by the links in issues: only that list is left.
+    list: Option<String>,
+}
+
+impl UnsubscribeParameters {
+    fn form_path(&self) -> String {
+        let mut path = format!(
+            "/subscriptions/unsubscribe?subscriber_id={}&token={}",
+            self.subscriber_id, self.token
+        );
+        if let Some(list) = &self.list {
+            path.push('&');
+            path.push_str(
+                &serde_urlencoded::to_string([("list", list)])
+                    .expect("A string pair is always URL-encodable"),
+            );
+        }
+        path
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            UnsubscribeError::InvalidToken => error_response(
+                self.status_code(),
+                "invalid_token",
+                &self.to_string(),
+                &[],
+            ),
+            UnsubscribeError::UnexpectedError(_) => internal_error_response(),
+        }
+    }
+}
+
+/// Ask for confirmation before unsubscribing: link scanners and mail
+/// previewers follow `GET` links, so only `POST` may change state.
+#[tracing::instrument(
+    name = "Show the unsubscribe page",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify_token(&parameters, &hmac_secret)?;
+    let list = resolve_list(&pool, &parameters).await?;
+    let question = match &list {
+        Some(list) => format!(
+            "Do you want to stop receiving {}?",
+            htmlescape::encode_minimal(&list.name)
+        ),
+        None => "Do you want to stop receiving our newsletter?".to_owned(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>{}</p>
+    <form action="{}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+    <p>
+        Or <a href="{}">change your preferences</a> instead, for instance to
+        pause delivery for a while.
+    </p>
+</body>
+</html>"#,
+            question,
+            htmlescape::encode_attribute(&parameters.form_path()),
+            htmlescape::encode_attribute(&preferences_link(
+                "",
+                parameters.subscriber_id,
+                &hmac_secret.0
+            )),
+        )))
+}
+
+/// Handles both the confirmation form and RFC 8058 one-click requests, which
+/// `POST` `List-Unsubscribe=One-Click` to the `List-Unsubscribe` URL.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify_token(&parameters, &hmac_secret)?;
+    let list = resolve_list(&pool, &parameters).await?;
+
+    unsubscribe_subscriber(&pool, parameters.subscriber_id, list.as_ref())
+        .await
+        .context("Failed to unsubscribe the subscriber.")?;
+
+    let message = match &list {
+        Some(list) => format!(
+            "You have been unsubscribed from {}.",
+            htmlescape::encode_minimal(&list.name)
+        ),
+        None => "You have been unsubscribed. You will not receive any more \
+            emails from us."
+            .to_owned(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>{}</p>
+</body>
+</html>"#,
+            message
+        )))
+}
+
+/// The list named in the link, if any. Links to a list that no longer
+/// exists are rejected rather than widened to every list.
+async fn resolve_list(
+    pool: &PgPool,
+    parameters: &UnsubscribeParameters,
+) -> Result<Option<MailingList>, UnsubscribeError> {
+    let Some(slug) = &parameters.list else {
+        return Ok(None);
+    };
+    get_list(pool, Some(slug))
+        .await
+        .context("Failed to look up the mailing list.")?
+        .map(Some)
+        .ok_or(UnsubscribeError::InvalidToken)
+}
+
+fn verify_token(
+    parameters: &UnsubscribeParameters,
+    hmac_secret: &HmacSecret,
+) -> Result<(), UnsubscribeError> {
+    if SubscriberToken::verify(
+        &parameters.token,
+        TokenPurpose::Unsubscribe,
+        parameters.subscriber_id,
+        &hmac_secret.0,
+    ) {
+        Ok(())
+    } else {
+        Err(UnsubscribeError::InvalidToken)
+    }
+}
+
+/// Leave `list`, or every list. Subscribers left on no list at all are
+/// unsubscribed altogether.
+#[tracing::instrument(
+    name = "Mark subscriber as unsubscribed",
+    skip(pool, list)
+)]
+async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list: Option<&MailingList>,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        update list_subscriptions set status = 'unsubscribed'
+        where subscriber_id = $1 and ($2::uuid is null or list_id = $2)
+        "#,
+        subscriber_id,
+        list.map(|l| l.list_id)
+    )
+    .execute(&mut *transaction)
+    .await?;
+    unsubscribe_if_on_no_list(&mut transaction, subscriber_id).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction))]
+pub async fn unsubscribe_if_on_no_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        update subscriptions set status = 'unsubscribed'
+        where id = $1 and not exists (
+            select 1 from list_subscriptions
+            where
+                subscriber_id = $1 and
+                status in ('pending_confirmation', 'confirmed')
+        )
+        "#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
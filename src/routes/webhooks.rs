@@ -0,0 +1,234 @@
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError, http::StatusCode, web,
+};
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::{
+    api_error::{
+        error_response, internal_error_response, unauthorized_response,
+    },
+    authentication::{Credentials, basic_authentication},
+    configuration::WebhookSettings,
+    domain::normalize_email,
+    routes::error_chain_fmt,
+};
+
+/// The webhook events we act upon, as sent by Postmark.
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+pub enum ProviderEvent {
+    Bounce(BounceEvent),
+    SpamComplaint(SpamComplaintEvent),
+    Delivery(DeliveryEvent),
+    /// Opens, clicks and anything else we did not subscribe to.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BounceEvent {
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    #[serde(rename = "Type")]
+    pub bounce_type: String,
+    pub email: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SpamComplaintEvent {
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    pub email: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeliveryEvent {
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    pub recipient: String,
+}
+
+/// Bounce types telling us the address will never accept our mail.
+const HARD_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::ValidationError(message) => error_response(
+                StatusCode::BAD_REQUEST,
+                "validation_error",
+                message,
+                &[],
+            ),
+            WebhookError::UnexpectedError(_) => internal_error_response(),
+            WebhookError::AuthError(_) => unauthorized_response("webhooks"),
+        }
+    }
+}
+
+/// Ingest bounce, spam complaint and delivery events from the provider.
+///
+/// Every event is stored as received. Hard bounces and spam complaints also
+/// move the subscriber out of `confirmed`, so that we stop mailing them.
+#[tracing::instrument(
+    name = "Ingest an email provider webhook",
+    skip(payload, pool, settings, request),
+    fields(record_type=tracing::field::Empty)
+)]
+pub async fn email_provider_webhook(
+    payload: web::Json<serde_json::Value>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, WebhookError> {
+    let credentials = basic_authentication(request.headers())
+        .map_err(WebhookError::AuthError)?;
+    verify_credentials(&credentials, &settings)
+        .map_err(WebhookError::AuthError)?;
+
+    let payload = payload.into_inner();
+    let record_type = payload["RecordType"]
+        .as_str()
+        .ok_or_else(|| {
+            WebhookError::ValidationError(
+                "The 'RecordType' field was missing.".into(),
+            )
+        })?
+        .to_owned();
+    tracing::Span::current()
+        .record("record_type", tracing::field::display(&record_type));
+    let event = serde_json::from_value::<ProviderEvent>(payload.clone())
+        .map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+
+    let (message_id, recipient) = match &event {
+        ProviderEvent::Bounce(e) => {
+            (e.message_id.as_deref(), Some(e.email.as_str()))
+        }
+        ProviderEvent::SpamComplaint(e) => {
+            (e.message_id.as_deref(), Some(e.email.as_str()))
+        }
+        ProviderEvent::Delivery(e) => {
+            (e.message_id.as_deref(), Some(e.recipient.as_str()))
+        }
+        ProviderEvent::Other => (None, None),
+    };
+    store_event(&pool, &record_type, message_id, recipient, &payload)
+        .await
+        .context("Failed to store the webhook event.")?;
+
+    match &event {
+        ProviderEvent::Bounce(e)
+            if HARD_BOUNCE_TYPES.contains(&e.bounce_type.as_str()) =>
+        {
+            deactivate_subscriber(&pool, &e.email, "bounced")
+                .await
+                .context("Failed to deactivate a bounced subscriber.")?;
+        }
+        ProviderEvent::SpamComplaint(e) => {
+            deactivate_subscriber(&pool, &e.email, "complained")
+                .await
+                .context("Failed to deactivate a complaining subscriber.")?;
+        }
+        _ => {}
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn verify_credentials(
+    credentials: &Credentials,
+    settings: &WebhookSettings,
+) -> Result<(), anyhow::Error> {
+    let username_matches = credentials
+        .username
+        .as_bytes()
+        .ct_eq(settings.username.as_bytes());
+    let password_matches = credentials
+        .password
+        .expose_secret()
+        .as_bytes()
+        .ct_eq(settings.password.expose_secret().as_bytes());
+    if bool::from(username_matches & password_matches) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Invalid webhook credentials."))
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn store_event(
+    pool: &PgPool,
+    record_type: &str,
+    provider_message_id: Option<&str>,
+    recipient: Option<&str>,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        insert into email_provider_events (
+            event_id,
+            record_type,
+            provider_message_id,
+            recipient,
+            payload,
+            received_at
+        )
+        values ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        record_type,
+        provider_message_id,
+        recipient,
+        payload,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn deactivate_subscriber(
+    pool: &PgPool,
+    email: &str,
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        update subscriptions
+        set status = $2
+        where
+            email_normalized = $1 and
+            status in ('pending_confirmation', 'confirmed')
+        "#,
+        normalize_email(email),
+        status
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{
+    LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+    generate_session_key,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+type SessionState = HashMap<String, String>;
+
+/// Server-side session storage backed by the `sessions` table.
+///
+/// The cookie only carries the session key; the state itself never leaves
+/// the database. Rows past their `expires_at` are treated as missing and are
+/// purged whenever a new session is created.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<(), sqlx::Error> {
+        sqlx::query!("delete from sessions where expires_at < now()")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+fn expires_at(ttl: &Duration) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            select state
+            from sessions
+            where session_key = $1 and expires_at > now()
+            "#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load session state.")
+        .map_err(LoadError::Other)?;
+
+        row.map(|r| serde_json::from_value(r.state))
+            .transpose()
+            .context("Failed to deserialize session state.")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize session state.")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+
+        sqlx::query!(
+            r#"
+            insert into sessions (session_key, state, expires_at)
+            values ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save session state.")
+        .map_err(SaveError::Other)?;
+
+        if let Err(e) = self.delete_expired_sessions().await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to purge expired sessions.");
+        }
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize session state.")
+            .map_err(UpdateError::Serialization)?;
+
+        let result = sqlx::query!(
+            r#"
+            update sessions
+            set state = $2, expires_at = $3
+            where session_key = $1 and expires_at > now()
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session state.")
+        .map_err(UpdateError::Other)?;
+
+        if result.rows_affected() == 0 {
+            // The session expired (or was deleted) in the meantime: start a
+            // fresh one rather than resurrecting the old key.
+            return self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            });
+        }
+
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "update sessions set expires_at = $2 where session_key = $1",
+            session_key.as_ref(),
+            expires_at(ttl)
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session expiry.")?;
+        Ok(())
+    }
+
+    async fn delete(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "delete from sessions where session_key = $1",
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete session.")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+//! Keeping automated signups out without an external CAPTCHA: a honeypot
+//! field that only bots fill in, and an optional proof-of-work challenge.
+//!
+//! Challenges are stateless: `GET /subscriptions/challenge` hands out a
+//! random nonce and an expiry, signed with the application's HMAC secret.
+//! The client must find a `solution` such that
+//! `SHA-256("{challenge}:{solution}")` starts with `difficulty` zero bits.
+//! Only spent nonces are stored, so that a solution cannot be replayed.
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::configuration::ProofOfWorkSettings;
+
+/// Longer solutions are rejected without being hashed.
+const MAX_SOLUTION_LENGTH: usize = 64;
+
+/// The anti-bot fields of the signup form.
+#[derive(Default, serde::Deserialize)]
+pub struct BotCheckFields {
+    /// The honeypot: hidden from humans by the form, so only bots fill it in.
+    #[serde(default)]
+    pub website: String,
+    pub pow_challenge: Option<String>,
+    pub pow_solution: Option<String>,
+}
+
+/// What a signup looks like it came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Human,
+    /// Answer as if the signup went through, without acting on it, so that
+    /// bots get no hint they were caught.
+    Bot,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignupCheckError {
+    #[error("{0}")]
+    InvalidProofOfWork(&'static str),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// A challenge as handed out to clients.
+#[derive(serde::Serialize)]
+pub struct IssuedChallenge {
+    pub challenge: String,
+    pub difficulty: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A challenge whose signature checked out.
+#[derive(Debug, PartialEq)]
+struct Challenge {
+    nonce: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl Challenge {
+    fn generate(ttl: TimeDelta) -> Self {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        // Drop sub-second precision, which does not survive the round trip.
+        let expires_at =
+            DateTime::from_timestamp((Utc::now() + ttl).timestamp(), 0)
+                .expect("The expiry is a valid timestamp.");
+        Self {
+            nonce: hex::encode(nonce),
+            expires_at,
+        }
+    }
+
+    /// `{nonce}.{expires_at}.{signature}`, with the expiry as a Unix
+    /// timestamp.
+    fn sign(&self, secret: &SecretString) -> String {
+        let payload = self.payload();
+        let signature = mac(&payload, secret).finalize().into_bytes();
+        format!("{}.{}", payload, hex::encode(signature))
+    }
+
+    /// Check the signature of `challenge` in constant time.
+    fn verify(challenge: &str, secret: &SecretString) -> Option<Self> {
+        let (payload, signature) = challenge.rsplit_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        mac(payload, secret).verify_slice(&signature).ok()?;
+
+        let (nonce, expires_at) = payload.split_once('.')?;
+        let expires_at = DateTime::from_timestamp(expires_at.parse().ok()?, 0)?;
+        Some(Self {
+            nonce: nonce.to_owned(),
+            expires_at,
+        })
+    }
+
+    fn payload(&self) -> String {
+        format!("{}.{}", self.nonce, self.expires_at.timestamp())
+    }
+}
+
+fn mac(payload: &str, secret: &SecretString) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+    mac.update(b"proof_of_work:");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Whether `solution` solves `challenge` at the given difficulty.
+pub fn is_solution(challenge: &str, solution: &str, difficulty: u32) -> bool {
+    let hash = Sha256::new()
+        .chain_update(challenge.as_bytes())
+        .chain_update(b":")
+        .chain_update(solution.as_bytes())
+        .finalize();
+    leading_zero_bits(&hash) >= difficulty
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in bytes {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+pub struct SignupProtection {
+    settings: ProofOfWorkSettings,
+    secret: SecretString,
+    pool: PgPool,
+}
+
+impl SignupProtection {
+    pub fn new(
+        settings: ProofOfWorkSettings,
+        secret: SecretString,
+        pool: PgPool,
+    ) -> Self {
+        Self {
+            settings,
+            secret,
+            pool,
+        }
+    }
+
+    pub fn issue_challenge(&self) -> IssuedChallenge {
+        let ttl =
+            TimeDelta::seconds(self.settings.challenge_ttl_seconds as i64);
+        let challenge = Challenge::generate(ttl);
+        IssuedChallenge {
+            challenge: challenge.sign(&self.secret),
+            difficulty: self.settings.difficulty,
+            expires_at: challenge.expires_at,
+        }
+    }
+
+    /// Look for signs of a bot, spending the proof-of-work challenge if
+    /// there is one.
+    #[tracing::instrument(name = "Checking a signup for bots.", skip_all)]
+    pub async fn check(
+        &self,
+        fields: &BotCheckFields,
+    ) -> Result<Verdict, SignupCheckError> {
+        if !fields.website.is_empty() {
+            tracing::info!("Rejected a signup that filled in the honeypot.");
+            return Ok(Verdict::Bot);
+        }
+        if !self.settings.enabled {
+            return Ok(Verdict::Human);
+        }
+
+        let (Some(challenge), Some(solution)) =
+            (&fields.pow_challenge, &fields.pow_solution)
+        else {
+            return Err(SignupCheckError::InvalidProofOfWork(
+                "A solved proof-of-work challenge is required.",
+            ));
+        };
+        let Some(verified) = Challenge::verify(challenge, &self.secret) else {
+            return Err(SignupCheckError::InvalidProofOfWork(
+                "The proof-of-work challenge is invalid.",
+            ));
+        };
+        let now = Utc::now();
+        if verified.expires_at <= now {
+            return Err(SignupCheckError::InvalidProofOfWork(
+                "The proof-of-work challenge has expired.",
+            ));
+        }
+        if solution.len() > MAX_SOLUTION_LENGTH
+            || !is_solution(challenge, solution, self.settings.difficulty)
+        {
+            return Err(SignupCheckError::InvalidProofOfWork(
+                "The proof-of-work solution is incorrect.",
+            ));
+        }
+        if !self.spend(&verified, now).await? {
+            return Err(SignupCheckError::InvalidProofOfWork(
+                "The proof-of-work challenge has already been used.",
+            ));
+        }
+        Ok(Verdict::Human)
+    }
+
+    /// Record the challenge as used. Returns `false` if it already was.
+    async fn spend(
+        &self,
+        challenge: &Challenge,
+        now: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        let spent = sqlx::query!(
+            r#"
+            insert into proof_of_work_challenges (nonce, expires_at)
+            values ($1, $2)
+            on conflict (nonce) do nothing
+            "#,
+            challenge.nonce,
+            challenge.expires_at
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to record a spent proof-of-work challenge.")?
+        .rows_affected()
+            == 1;
+
+        // Every now and then, forget challenges that can no longer be used.
+        if rand::thread_rng().gen_bool(0.01) {
+            sqlx::query!(
+                "delete from proof_of_work_challenges where expires_at < $1",
+                now
+            )
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired proof-of-work challenges.")?;
+        }
+
+        Ok(spent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Challenge, is_solution, leading_zero_bits};
+    use chrono::TimeDelta;
+    use secrecy::SecretString;
+
+    fn secret() -> SecretString {
+        SecretString::from("a-secret")
+    }
+
+    #[test]
+    fn a_signed_challenge_verifies() {
+        let challenge = Challenge::generate(TimeDelta::minutes(5));
+
+        let signed = challenge.sign(&secret());
+
+        assert_eq!(Challenge::verify(&signed, &secret()), Some(challenge));
+    }
+
+    #[test]
+    fn a_tampered_challenge_does_not_verify() {
+        let challenge = Challenge::generate(TimeDelta::minutes(5));
+        let signed = challenge.sign(&secret());
+        let (nonce, rest) = signed.split_once('.').unwrap();
+        let tampered = format!("{}0.{}", &nonce[1..], rest);
+
+        assert_eq!(Challenge::verify(&tampered, &secret()), None);
+        assert_eq!(
+            Challenge::verify(&signed, &SecretString::from("another")),
+            None
+        );
+        assert_eq!(Challenge::verify("garbage", &secret()), None);
+    }
+
+    #[test]
+    fn leading_zero_bits_are_counted_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x10, 0x00]), 11);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn a_brute_forced_solution_is_accepted() {
+        let challenge = "some-challenge";
+        let solution = (0u64..)
+            .map(|n| n.to_string())
+            .find(|solution| is_solution(challenge, solution, 8))
+            .unwrap();
+
+        assert!(is_solution(challenge, &solution, 8));
+        assert!(is_solution(challenge, &solution, 0));
+    }
+}
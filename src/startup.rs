@@ -1,12 +1,41 @@
-use actix_web::{App, HttpServer, dev::Server, web};
+use actix_session::{SessionMiddleware, config::BrowserSession};
+use actix_web::{
+    App, HttpServer,
+    cookie::{Key, time::Duration},
+    dev::Server,
+    middleware::from_fn,
+    web,
+};
+use actix_web_flash_messages::{
+    FlashMessagesFramework, storage::CookieMessageStore,
+};
+use secrecy::{ExposeSecret, SecretString};
 use sqlx::{PgPool, postgres::PgPoolOptions};
-use std::net::TcpListener;
+use std::{net::TcpListener, path::PathBuf, sync::Arc};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
-    routes::{confirm, greet, health_check, publish_newsletter, subscribe},
+    api_error::{
+        form_error_handler, json_error_handler, query_error_handler,
+        with_request_context,
+    },
+    authentication::reject_anonymous_users,
+    configuration::{DatabaseSettings, Environment, Settings},
+    email_client::EmailSender,
+    email_policy::EmailPolicy,
+    rate_limit::{RateLimiter, rate_limit},
+    routes::{
+        admin_dashboard, confirm, create_list, create_subscription,
+        dead_letters, email_provider_webhook, erase_data, erase_data_form,
+        erase_subscriber_data_for_admin, export_data,
+        export_subscriber_data_for_admin, get_lists, greet, health_check,
+        issue_challenge, log_out, login, login_form, outbox, outbox_message,
+        preferences_form, publish_newsletter, request_subscriber_data,
+        requeue_dead_letter, resend_confirmation, subscribe, unsubscribe,
+        unsubscribe_form, update_preferences,
+    },
+    session_store::PgSessionStore,
+    signup_protection::SignupProtection,
 };
 
 pub struct Application {
@@ -20,19 +49,8 @@ impl Application {
     ) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
 
-        let sender = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
         let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.clone().client();
 
         let address = format!(
             "{}:{}",
@@ -49,12 +67,8 @@ impl Application {
         );
         let port = listener.local_addr().unwrap().port();
 
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            configuration.application.base_url,
-        )?;
+        let server =
+            run(listener, connection_pool, email_client, configuration)?;
 
         Ok(Self { port, server })
     }
@@ -70,26 +84,197 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct HmacSecret(pub SecretString);
+
+pub struct ConfirmationRedirectUrl(pub Option<String>);
+
+pub struct OutboxDirectory(pub PathBuf);
+
 fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    email_client: Arc<dyn EmailSender>,
+    configuration: Settings,
 ) -> Result<Server, std::io::Error> {
+    let Settings {
+        application,
+        email_client: email_client_settings,
+        environment,
+        ..
+    } = configuration;
+    let hmac_secret = application.hmac_secret;
+    let session_ttl = Duration::minutes(application.session_ttl_minutes);
+    // Captured mail is only ever browsable on a developer's machine.
+    let outbox_directory = match environment {
+        Environment::Local => email_client_settings.outbox.map(|o| o.directory),
+        Environment::Production => None,
+    };
+
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework =
+        FlashMessagesFramework::builder(message_store).build();
+    let session_store = PgSessionStore::new(db_pool.clone());
+    let rate_limiter = web::Data::new(RateLimiter::new(
+        application.rate_limit,
+        db_pool.clone(),
+    ));
+    let signup_protection = web::Data::new(SignupProtection::new(
+        application.proof_of_work,
+        hmac_secret.clone(),
+        db_pool.clone(),
+    ));
+    let email_policy =
+        web::Data::new(EmailPolicy::new(application.email_policy));
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client = web::Data::from(email_client);
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let confirmation_redirect_url = web::Data::new(ConfirmationRedirectUrl(
+        application.confirmation_redirect_url,
+    ));
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let outbox_directory =
+        outbox_directory.map(|d| web::Data::new(OutboxDirectory(d)));
+    let webhook_settings = web::Data::new(email_client_settings.webhook);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(message_framework.clone())
+            .wrap(
+                SessionMiddleware::builder(
+                    session_store.clone(),
+                    secret_key.clone(),
+                )
+                .session_lifecycle(
+                    BrowserSession::default().state_ttl(session_ttl),
+                )
+                .build(),
+            )
+            .wrap(from_fn(with_request_context))
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/dead_letters", web::get().to(dead_letters))
+                    .route(
+                        "/dead_letters/requeue",
+                        web::post().to(requeue_dead_letter),
+                    )
+                    .route("/logout", web::post().to(log_out)),
+            )
             .route("/newsletters", web::post().to(publish_newsletter))
-            .route("/subscriptions", web::post().to(subscribe))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(rate_limit))
+                    .route(web::post().to(subscribe)),
+            )
+            .service(
+                web::scope("/api/v1")
+                    .service(
+                        web::resource("/subscriptions")
+                            .wrap(from_fn(rate_limit))
+                            .route(web::post().to(create_subscription)),
+                    )
+                    .service(
+                        web::resource("/lists/{list}/subscriptions")
+                            .wrap(from_fn(rate_limit))
+                            .route(web::post().to(create_subscription)),
+                    )
+                    .service(
+                        web::resource("/admin/lists")
+                            .route(web::get().to(get_lists))
+                            .route(web::post().to(create_list)),
+                    )
+                    .route(
+                        "/admin/subscriber_data/export",
+                        web::post().to(export_subscriber_data_for_admin),
+                    )
+                    .route(
+                        "/admin/subscriber_data/erase",
+                        web::post().to(erase_subscriber_data_for_admin),
+                    ),
+            )
+            .route("/subscriptions/challenge", web::get().to(issue_challenge))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .service(
+                web::resource("/subscriptions/data")
+                    .wrap(from_fn(rate_limit))
+                    .route(web::post().to(request_subscriber_data)),
+            )
+            .route("/subscriptions/data/export", web::get().to(export_data))
+            .route("/subscriptions/data/erase", web::get().to(erase_data_form))
+            .route("/subscriptions/data/erase", web::post().to(erase_data))
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(preferences_form),
+            )
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(update_preferences),
+            )
+            .service(
+                web::resource("/subscriptions/resend")
+                    .wrap(from_fn(rate_limit))
+                    .route(web::post().to(resend_confirmation)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .service(
+                web::scope("/lists/{list}")
+                    .service(
+                        web::resource("/subscriptions")
+                            .wrap(from_fn(rate_limit))
+                            .route(web::post().to(subscribe)),
+                    )
+                    .route("/subscriptions/confirm", web::get().to(confirm))
+                    .service(
+                        web::resource("/subscriptions/resend")
+                            .wrap(from_fn(rate_limit))
+                            .route(web::post().to(resend_confirmation)),
+                    ),
+            )
+            .route(
+                "/webhooks/email-provider",
+                web::post().to(email_provider_webhook),
+            )
+            .configure(|cfg| {
+                if let Some(outbox_directory) = &outbox_directory {
+                    cfg.service(
+                        web::scope("/dev")
+                            .app_data(outbox_directory.clone())
+                            .route("/outbox", web::get().to(outbox))
+                            .route(
+                                "/outbox/{id}",
+                                web::get().to(outbox_message),
+                            ),
+                    );
+                }
+            })
             .route("/{name}", web::get().to(greet))
+            .app_data(
+                web::JsonConfig::default().error_handler(json_error_handler),
+            )
+            .app_data(
+                web::FormConfig::default().error_handler(form_error_handler),
+            )
+            .app_data(
+                web::QueryConfig::default().error_handler(query_error_handler),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_policy.clone())
             .app_data(base_url.clone())
+            .app_data(confirmation_redirect_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(signup_protection.clone())
+            .app_data(webhook_settings.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,388 @@
+//! Everything we hold about an email address: handed over when its owner
+//! asks for a copy, and erased when they ask us to forget them.
+//!
+//! Delivery history is keyed by the address the issue was sent to, so rows
+//! are matched case-insensitively against every spelling we know of.
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::{Executor, PgPool, Postgres};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+/// A copy of all the data held about an address.
+#[derive(serde::Serialize)]
+pub struct SubscriberDataExport {
+    pub email: String,
+    pub exported_at: DateTime<Utc>,
+    pub subscription: Option<SubscriptionRecord>,
+    pub list_subscriptions: Vec<ListSubscriptionRecord>,
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub queued_deliveries: Vec<QueuedDeliveryRecord>,
+    pub deliveries: Vec<DeliveryRecord>,
+    pub failed_deliveries: Vec<FailedDeliveryRecord>,
+    pub provider_events: Vec<ProviderEventRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub email_format: String,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListSubscriptionRecord {
+    pub list: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// Confirmation tokens are only stored as digests, which are of no use to
+/// anyone and are left out.
+#[derive(serde::Serialize)]
+pub struct SubscriptionTokenRecord {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct QueuedDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub subscriber_email: String,
+    pub n_retries: i16,
+    pub execute_after: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub subscriber_email: String,
+    pub provider_message_id: Option<String>,
+    pub delivered_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct FailedDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub subscriber_email: String,
+    pub n_attempts: i16,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ProviderEventRecord {
+    pub record_type: String,
+    pub provider_message_id: Option<String>,
+    pub recipient: Option<String>,
+    pub payload: serde_json::Value,
+    pub received_at: DateTime<Utc>,
+}
+
+impl SubscriberDataExport {
+    fn is_empty(&self) -> bool {
+        self.subscription.is_none()
+            && self.list_subscriptions.is_empty()
+            && self.subscription_tokens.is_empty()
+            && self.queued_deliveries.is_empty()
+            && self.deliveries.is_empty()
+            && self.failed_deliveries.is_empty()
+            && self.provider_events.is_empty()
+    }
+}
+
+/// The digest kept in `suppressed_emails` for `email`.
+///
+/// Keyed with the HMAC secret: a plain digest of an address can be matched
+/// against a list of known addresses by anyone who can read the table.
+fn suppression_hash(email: &SubscriberEmail, secret: &SecretString) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+    mac.update(b"suppressed_email:");
+    mac.update(email.normalized().as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Whether the data of `email` was erased, in which case nobody may sign it
+/// up again.
+pub async fn is_suppressed<'a, E>(
+    executor: E,
+    email: &SubscriberEmail,
+    secret: &SecretString,
+) -> Result<bool, sqlx::Error>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let row = sqlx::query!(
+        "select exists(select 1 from suppressed_emails where email_hash = $1)",
+        suppression_hash(email, secret)
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(row.exists.unwrap_or(false))
+}
+
+/// The address of a subscriber, if they are still on file.
+#[tracing::instrument(skip(pool))]
+pub async fn get_subscriber_email(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberEmail>, anyhow::Error> {
+    let row = sqlx::query!(
+        "select email from subscriptions where id = $1",
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscriber's email.")?;
+    row.map(|r| SubscriberEmail::parse(r.email))
+        .transpose()
+        .map_err(anyhow::Error::msg)
+}
+
+/// The lowercased spellings of `email` that delivery rows may be keyed by.
+async fn known_addresses(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut addresses =
+        vec![email.normalized().to_owned(), email.as_ref().to_lowercase()];
+    let stored = sqlx::query!(
+        "select email from subscriptions where email_normalized = $1",
+        email.normalized()
+    )
+    .fetch_optional(pool)
+    .await?;
+    addresses.extend(stored.map(|r| r.email.to_lowercase()));
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+/// Gather everything held about `email`. Returns `None` if there is nothing.
+#[tracing::instrument(skip(pool))]
+pub async fn export_subscriber_data(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<SubscriberDataExport>, anyhow::Error> {
+    let addresses = known_addresses(pool, email)
+        .await
+        .context("Failed to look up the subscriber's addresses.")?;
+
+    let subscription = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        select
+            id, email, name, status, subscribed_at, email_format, paused_until
+        from subscriptions
+        where email_normalized = $1
+        "#,
+        email.normalized()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscription.")?;
+    let list_subscriptions = sqlx::query_as!(
+        ListSubscriptionRecord,
+        r#"
+        select l.slug as list, m.status, m.subscribed_at
+        from list_subscriptions m
+        join lists l using (list_id)
+        join subscriptions s on s.id = m.subscriber_id
+        where s.email_normalized = $1
+        order by m.subscribed_at
+        "#,
+        email.normalized()
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the list subscriptions.")?;
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+        select t.created_at, t.expires_at, t.consumed_at
+        from subscription_tokens t
+        join subscriptions s on s.id = t.subscriber_id
+        where s.email_normalized = $1
+        order by t.created_at
+        "#,
+        email.normalized()
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the subscription tokens.")?;
+    let queued_deliveries = sqlx::query_as!(
+        QueuedDeliveryRecord,
+        r#"
+        select
+            q.newsletter_issue_id, i.title, q.subscriber_email,
+            q.n_retries, q.execute_after
+        from issue_delivery_queue q
+        join newsletter_issues i using (newsletter_issue_id)
+        where lower(q.subscriber_email) = any($1)
+        order by i.published_at
+        "#,
+        &addresses
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the queued deliveries.")?;
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        select
+            d.newsletter_issue_id, i.title, d.subscriber_email,
+            d.provider_message_id, d.delivered_at
+        from issue_deliveries d
+        join newsletter_issues i using (newsletter_issue_id)
+        where lower(d.subscriber_email) = any($1)
+        order by d.delivered_at
+        "#,
+        &addresses
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the deliveries.")?;
+    let failed_deliveries = sqlx::query_as!(
+        FailedDeliveryRecord,
+        r#"
+        select
+            f.newsletter_issue_id, i.title, f.subscriber_email,
+            f.n_attempts, f.last_error, f.failed_at
+        from issue_delivery_dead_letters f
+        join newsletter_issues i using (newsletter_issue_id)
+        where lower(f.subscriber_email) = any($1)
+        order by f.failed_at
+        "#,
+        &addresses
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the failed deliveries.")?;
+    let provider_events = sqlx::query_as!(
+        ProviderEventRecord,
+        r#"
+        select record_type, provider_message_id, recipient, payload, received_at
+        from email_provider_events
+        where lower(recipient) = any($1)
+        order by received_at
+        "#,
+        &addresses
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the email provider events.")?;
+
+    let export = SubscriberDataExport {
+        email: email.normalized().to_owned(),
+        exported_at: Utc::now(),
+        subscription,
+        list_subscriptions,
+        subscription_tokens,
+        queued_deliveries,
+        deliveries,
+        failed_deliveries,
+        provider_events,
+    };
+    Ok((!export.is_empty()).then_some(export))
+}
+
+/// Delete everything held about `email`, keeping only a digest of the
+/// address so that it cannot be signed up again.
+#[tracing::instrument(skip(pool))]
+pub async fn erase_subscriber_data(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    secret: &SecretString,
+) -> Result<(), anyhow::Error> {
+    let addresses = known_addresses(pool, email)
+        .await
+        .context("Failed to look up the subscriber's addresses.")?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    // Events about our deliveries may not carry the recipient.
+    sqlx::query!(
+        r#"
+        delete from email_provider_events
+        where
+            lower(recipient) = any($1) or
+            provider_message_id in (
+                select provider_message_id from issue_deliveries
+                where lower(subscriber_email) = any($1)
+            )
+        "#,
+        &addresses
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the email provider events.")?;
+    sqlx::query!(
+        "delete from issue_delivery_queue where lower(subscriber_email) = any($1)",
+        &addresses
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the queued deliveries.")?;
+    sqlx::query!(
+        "delete from issue_deliveries where lower(subscriber_email) = any($1)",
+        &addresses
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the deliveries.")?;
+    sqlx::query!(
+        "delete from issue_delivery_dead_letters where lower(subscriber_email) = any($1)",
+        &addresses
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the failed deliveries.")?;
+    // Confirmation tokens and list subscriptions go with the subscription.
+    sqlx::query!(
+        "delete from subscriptions where email_normalized = $1",
+        email.normalized()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the subscription.")?;
+    sqlx::query!(
+        "delete from rate_limit_buckets where key = $1",
+        format!("email:{}", email.normalized())
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the rate limit bucket.")?;
+    sqlx::query!(
+        r#"
+        insert into suppressed_emails (email_hash, suppressed_at)
+        values ($1, now())
+        on conflict (email_hash) do nothing
+        "#,
+        suppression_hash(email, secret)
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the suppressed address.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the erasure of a subscriber's data.")?;
+    Ok(())
+}
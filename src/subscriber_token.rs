@@ -0,0 +1,379 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// What a signed link allows its holder to do.
+///
+/// The purpose is part of the signed payload, so a token issued for one
+/// action cannot be replayed against another.
+#[derive(Clone, Copy, Debug)]
+pub enum TokenPurpose {
+    Unsubscribe,
+    ManagePreferences,
+    ExportData,
+    EraseData,
+}
+
+impl TokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Unsubscribe => "unsubscribe",
+            TokenPurpose::ManagePreferences => "preferences",
+            TokenPurpose::ExportData => "export_data",
+            TokenPurpose::EraseData => "erase_data",
+        }
+    }
+}
+
+/// A stateless, per-subscriber token: an HMAC-SHA256 tag over the purpose
+/// and the subscriber id, keyed with the application's HMAC secret.
+///
+/// Tokens that must expire also cover the time they were issued at, which
+/// travels next to them in the link.
+#[derive(Debug)]
+pub struct SubscriberToken(String);
+
+impl SubscriberToken {
+    pub fn sign(
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        secret: &SecretString,
+    ) -> Self {
+        Self::sign_payload(purpose, subscriber_id, None, secret)
+    }
+
+    /// Check `token` in constant time.
+    pub fn verify(
+        token: &str,
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        secret: &SecretString,
+    ) -> bool {
+        Self::verify_payload(token, purpose, subscriber_id, None, secret)
+    }
+
+    /// Sign a token that is only valid along with `issued_at`, a Unix
+    /// timestamp. Callers decide how long it lasts.
+    pub fn sign_issued_at(
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        issued_at: i64,
+        secret: &SecretString,
+    ) -> Self {
+        Self::sign_payload(purpose, subscriber_id, Some(issued_at), secret)
+    }
+
+    /// Check, in constant time, that `token` was signed for `issued_at`.
+    pub fn verify_issued_at(
+        token: &str,
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        issued_at: i64,
+        secret: &SecretString,
+    ) -> bool {
+        Self::verify_payload(
+            token,
+            purpose,
+            subscriber_id,
+            Some(issued_at),
+            secret,
+        )
+    }
+
+    fn sign_payload(
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        issued_at: Option<i64>,
+        secret: &SecretString,
+    ) -> Self {
+        let tag = mac(purpose, subscriber_id, issued_at, secret)
+            .finalize()
+            .into_bytes();
+        Self(hex::encode(tag))
+    }
+
+    fn verify_payload(
+        token: &str,
+        purpose: TokenPurpose,
+        subscriber_id: Uuid,
+        issued_at: Option<i64>,
+        secret: &SecretString,
+    ) -> bool {
+        let Ok(tag) = hex::decode(token) else {
+            return false;
+        };
+        mac(purpose, subscriber_id, issued_at, secret)
+            .verify_slice(&tag)
+            .is_ok()
+    }
+}
+
+impl AsRef<str> for SubscriberToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn mac(
+    purpose: TokenPurpose,
+    subscriber_id: Uuid,
+    issued_at: Option<i64>,
+    secret: &SecretString,
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+    mac.update(purpose.as_str().as_bytes());
+    mac.update(b":");
+    mac.update(subscriber_id.as_bytes());
+    if let Some(issued_at) = issued_at {
+        mac.update(b":");
+        mac.update(&issued_at.to_be_bytes());
+    }
+    mac
+}
+
+/// `{base_url}{path}` with the subscriber id and a token for `purpose`.
+fn signed_link(
+    base_url: &str,
+    path: &str,
+    purpose: TokenPurpose,
+    subscriber_id: Uuid,
+    secret: &SecretString,
+) -> String {
+    let token = SubscriberToken::sign(purpose, subscriber_id, secret);
+    format!(
+        "{}{}?subscriber_id={}&token={}",
+        base_url,
+        path,
+        subscriber_id,
+        token.as_ref()
+    )
+}
+
+pub fn unsubscribe_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    secret: &SecretString,
+) -> String {
+    signed_link(
+        base_url,
+        "/subscriptions/unsubscribe",
+        TokenPurpose::Unsubscribe,
+        subscriber_id,
+        secret,
+    )
+}
+
+/// Unsubscribes from `list_slug` only, rather than from every list.
+pub fn list_unsubscribe_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    list_slug: &str,
+    secret: &SecretString,
+) -> String {
+    format!(
+        "{}&{}",
+        unsubscribe_link(base_url, subscriber_id, secret),
+        serde_urlencoded::to_string([("list", list_slug)])
+            .expect("A string pair is always URL-encodable")
+    )
+}
+
+pub fn preferences_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    secret: &SecretString,
+) -> String {
+    signed_link(
+        base_url,
+        "/subscriptions/preferences",
+        TokenPurpose::ManagePreferences,
+        subscriber_id,
+        secret,
+    )
+}
+
+pub fn data_export_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    issued_at: i64,
+    secret: &SecretString,
+) -> String {
+    issued_link(
+        base_url,
+        "/subscriptions/data/export",
+        TokenPurpose::ExportData,
+        subscriber_id,
+        issued_at,
+        secret,
+    )
+}
+
+pub fn data_erasure_link(
+    base_url: &str,
+    subscriber_id: Uuid,
+    issued_at: i64,
+    secret: &SecretString,
+) -> String {
+    issued_link(
+        base_url,
+        "/subscriptions/data/erase",
+        TokenPurpose::EraseData,
+        subscriber_id,
+        issued_at,
+        secret,
+    )
+}
+
+/// Like [`signed_link`], for a token that only lasts so long after
+/// `issued_at`.
+fn issued_link(
+    base_url: &str,
+    path: &str,
+    purpose: TokenPurpose,
+    subscriber_id: Uuid,
+    issued_at: i64,
+    secret: &SecretString,
+) -> String {
+    let token = SubscriberToken::sign_issued_at(
+        purpose,
+        subscriber_id,
+        issued_at,
+        secret,
+    );
+    format!(
+        "{}{}?subscriber_id={}&issued_at={}&token={}",
+        base_url,
+        path,
+        subscriber_id,
+        issued_at,
+        token.as_ref()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SubscriberToken, TokenPurpose, list_unsubscribe_link};
+    use secrecy::SecretString;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn secret() -> SecretString {
+        SecretString::from("a-very-secret-key")
+    }
+
+    #[test]
+    fn a_signed_token_is_verified() {
+        let id = Uuid::new_v4();
+        let token =
+            SubscriberToken::sign(TokenPurpose::Unsubscribe, id, &secret());
+        assert!(SubscriberToken::verify(
+            token.as_ref(),
+            TokenPurpose::Unsubscribe,
+            id,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = SubscriberToken::sign(
+            TokenPurpose::Unsubscribe,
+            Uuid::new_v4(),
+            &secret(),
+        );
+        assert!(!SubscriberToken::verify(
+            token.as_ref(),
+            TokenPurpose::Unsubscribe,
+            Uuid::new_v4(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let id = Uuid::new_v4();
+        let token = SubscriberToken::sign(
+            TokenPurpose::Unsubscribe,
+            id,
+            &SecretString::from("another-key"),
+        );
+        assert!(!SubscriberToken::verify(
+            token.as_ref(),
+            TokenPurpose::Unsubscribe,
+            id,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_token_for_another_purpose_is_rejected() {
+        let id = Uuid::new_v4();
+        let token =
+            SubscriberToken::sign(TokenPurpose::Unsubscribe, id, &secret());
+        assert!(!SubscriberToken::verify(
+            token.as_ref(),
+            TokenPurpose::ManagePreferences,
+            id,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_malformed_token_is_rejected() {
+        assert!(!SubscriberToken::verify(
+            "not-hex",
+            TokenPurpose::Unsubscribe,
+            Uuid::new_v4(),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn the_list_slug_round_trips_through_the_unsubscribe_link() {
+        let link = list_unsubscribe_link(
+            "http://localhost",
+            Uuid::new_v4(),
+            "weekly&token=x y",
+            &secret(),
+        );
+        let (_, query) = link.split_once('?').unwrap();
+        let parameters: HashMap<String, String> =
+            serde_urlencoded::from_str(query).unwrap();
+        assert_eq!(parameters["list"], "weekly&token=x y");
+        assert_ne!(parameters["token"], "x y");
+    }
+
+    #[test]
+    fn a_token_for_another_issue_time_is_rejected() {
+        let id = Uuid::new_v4();
+        let token = SubscriberToken::sign_issued_at(
+            TokenPurpose::ExportData,
+            id,
+            1_700_000_000,
+            &secret(),
+        );
+        assert!(SubscriberToken::verify_issued_at(
+            token.as_ref(),
+            TokenPurpose::ExportData,
+            id,
+            1_700_000_000,
+            &secret()
+        ));
+        assert!(!SubscriberToken::verify_issued_at(
+            token.as_ref(),
+            TokenPurpose::ExportData,
+            id,
+            1_800_000_000,
+            &secret()
+        ));
+        assert!(!SubscriberToken::verify(
+            token.as_ref(),
+            TokenPurpose::ExportData,
+            id,
+            &secret()
+        ));
+    }
+}
@@ -0,0 +1,19 @@
+use actix_web::HttpResponse;
+use actix_web::error::InternalError;
+use actix_web::http::header::LOCATION;
+
+use crate::api_error::internal_error_response;
+
+// Return an opaque 500 while preserving the error root's cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    InternalError::from_response(e, internal_error_response()).into()
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
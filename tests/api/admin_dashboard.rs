@@ -0,0 +1,65 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_app().await;
+
+    app.login_as_test_user().await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page.contains("<p><i>You have successfully logged out.</i></p>")
+    );
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn expired_sessions_are_rejected() {
+    let app = spawn_app().await;
+
+    app.login_as_test_user().await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+
+    sqlx::query!(
+        "update sessions set expires_at = now() - interval '1 minute'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_username_is_escaped_on_the_dashboard() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    sqlx::query!(
+        "update users set username = '<b>admin</b>' where user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let html_page = app.get_admin_dashboard_html().await;
+
+    assert!(html_page.contains("Welcome &lt;b&gt;admin&lt;/b&gt;!"));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_log_out() {
+    let app = spawn_app().await;
+
+    let response = app.post_logout().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
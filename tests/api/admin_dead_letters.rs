@@ -0,0 +1,94 @@
+use uuid::Uuid;
+
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+
+async fn insert_dead_letter(app: &TestApp, email: &str) -> Uuid {
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        "insert into newsletter_issues (
+            newsletter_issue_id, list_id, title, text_content, html_content,
+            published_at
+        )
+        select
+            $1, list_id, 'Issue title', 'text', '<p>html</p>', now()
+        from lists
+        where is_default",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "insert into issue_delivery_dead_letters (
+            newsletter_issue_id, subscriber_email, n_attempts, last_error,
+            failed_at
+        )
+        values ($1, $2, 5, 'HTTP status server error (503)', now())",
+        issue_id,
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    issue_id
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_failed_deliveries() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dead_letters().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_listed() {
+    let app = spawn_app().await;
+    insert_dead_letter(&app, "ursula_le_guin@gmail.com").await;
+    app.login_as_test_user().await;
+
+    let html_page = app.get_admin_dead_letters().await.text().await.unwrap();
+
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("HTTP status server error (503)"));
+}
+
+#[tokio::test]
+async fn requeueing_moves_the_delivery_back_onto_the_queue() {
+    let app = spawn_app().await;
+    let issue_id = insert_dead_letter(&app, "ursula_le_guin@gmail.com").await;
+    app.login_as_test_user().await;
+
+    let response = app
+        .post_requeue_dead_letter(&serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "subscriber_email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dead_letters");
+
+    let html_page = app.get_admin_dead_letters().await.text().await.unwrap();
+    assert!(
+        html_page.contains("<p><i>The delivery has been requeued.</i></p>")
+    );
+
+    let task = sqlx::query!(
+        "select newsletter_issue_id, subscriber_email, n_retries
+        from issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.newsletter_issue_id, issue_id);
+    assert_eq!(task.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(task.n_retries, 0);
+
+    let dead_letters = sqlx::query!(
+        "select count(*) as count from issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(dead_letters.count, Some(0));
+}
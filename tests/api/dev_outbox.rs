@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+use zero2prod::configuration::{
+    EmailProvider, Environment, OutboxSettings, Settings,
+};
+
+use crate::helpers::{TestApp, spawn_app_with};
+
+fn use_outbox(c: &mut Settings, directory: &Path) {
+    c.email_client.provider = EmailProvider::Outbox;
+    c.email_client.outbox = Some(OutboxSettings {
+        directory: directory.to_owned(),
+    });
+}
+
+fn outbox_directory() -> PathBuf {
+    std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()))
+}
+
+impl TestApp {
+    pub async fn get_dev_outbox(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/dev/outbox", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_listed_in_the_dev_outbox() {
+    // Prep
+    let directory = outbox_directory();
+    let app = spawn_app_with(|c| use_outbox(c, &directory)).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let response = app.get_dev_outbox().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("1 captured emails"));
+    assert!(html.contains("ursula_le_guin@gmail.com"));
+    assert!(html.contains("Email Confirmation"));
+}
+
+#[tokio::test]
+async fn captured_emails_can_be_read_from_the_dev_outbox() {
+    // Prep
+    let directory = outbox_directory();
+    let app = spawn_app_with(|c| use_outbox(c, &directory)).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let index = std::fs::read_to_string(directory.join("index.jsonl")).unwrap();
+    let entry: serde_json::Value =
+        serde_json::from_str(index.lines().next().unwrap()).unwrap();
+    let id = entry["id"].as_str().unwrap();
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/dev/outbox/{}", &app.address, id))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let message = response.text().await.unwrap();
+    assert!(message.contains("multipart/alternative"));
+    assert!(message.contains("Subject: Email Confirmation"));
+}
+
+#[tokio::test]
+async fn the_dev_outbox_is_not_available_in_production() {
+    // Prep
+    let directory = outbox_directory();
+    let app = spawn_app_with(|c| {
+        use_outbox(c, &directory);
+        c.environment = Environment::Production;
+    })
+    .await;
+
+    // Act
+    let response = app.get_dev_outbox().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -1,10 +1,25 @@
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHasher, Version,
+    password_hash::SaltString,
+};
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, SecretString};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
 use zero2prod::{
-    configuration::{DatabaseSettings, get_configuration},
+    configuration::{
+        DatabaseSettings, EmailProvider, Settings, WebhookSettings,
+        get_configuration,
+    },
+    email_client::EmailSender,
+    issue_delivery_worker::{ExecutionOutcome, try_execute_task},
     startup::{Application, get_connection_pool},
+    subscriber_token::preferences_link,
     telemetry::{get_subscriber, init_subscriber},
 };
 
@@ -55,6 +70,13 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 }
 
 pub(crate) async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after letting `customise` adjust its configuration.
+pub(crate) async fn spawn_app_with(
+    customise: impl FnOnce(&mut Settings),
+) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -62,11 +84,12 @@ pub(crate) async fn spawn_app() -> TestApp {
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration.");
 
-        c.database.database_name =
-            format!("{}{}", DB_PREFIX, Uuid::new_v4().to_string());
+        c.database.database_name = format!("{}{}", DB_PREFIX, Uuid::new_v4());
 
         c.application.port = 0;
+        c.email_client.provider = EmailProvider::Postmark;
         c.email_client.base_url = email_server.uri();
+        customise(&mut c);
         c
     };
 
@@ -78,14 +101,67 @@ pub(crate) async fn spawn_app() -> TestApp {
 
     let address = format!("http://127.0.0.1:{}", application.port());
     let port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
     let db_pool = get_connection_pool(&configuration.database);
 
-    TestApp {
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
+    let test_app = TestApp {
         address,
         db_pool,
         email_server,
         port,
+        test_user: TestUser::generate(),
+        api_client,
+        email_client: configuration.email_client.clone().client(),
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        webhook_settings: configuration.email_client.webhook,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+
+        sqlx::query!(
+            "insert into users (user_id, username, password_hash)
+            values ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
     }
 }
 
@@ -94,15 +170,51 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    pub email_client: Arc<dyn EmailSender>,
+    pub base_url: String,
+    pub hmac_secret: SecretString,
+    pub webhook_settings: WebhookSettings,
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_newsletters(
         &self,
         body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.post_newsletters_with_key(body, &Uuid::new_v4().to_string())
+            .await
+    }
+
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
     ) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .header("Idempotency-Key", idempotency_key)
             .json(&body)
             .send()
             .await
@@ -111,7 +223,33 @@ impl TestApp {
 
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_subscriptions(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_list_subscriptions(
+        &self,
+        list: &str,
+        body: String,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists/{}/subscriptions", &self.address, list))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -119,6 +257,234 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_admin_lists(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/admin/lists", &self.address))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_challenge(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions/challenge", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resend_confirmation(
+        &self,
+        body: String,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/resend", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_request(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/data", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_subscriber_data_export(
+        &self,
+        email: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/api/v1/admin/subscriber_data/export",
+                &self.address
+            ))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .json(&serde_json::json!({"email": email}))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_subscriber_data_erase(
+        &self,
+        email: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/api/v1/admin/subscriber_data/erase",
+                &self.address
+            ))
+            .basic_auth(
+                &self.test_user.username,
+                Some(&self.test_user.password),
+            )
+            .json(&serde_json::json!({"email": email}))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dead_letters(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_requeue_dead_letter<Body>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/dead_letters/requeue", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_email_provider_webhook(
+        &self,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email-provider", &self.address))
+            .basic_auth(
+                &self.webhook_settings.username,
+                Some(self.webhook_settings.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn login_as_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await;
+    }
+
+    pub fn get_unsubscribe_link(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> reqwest::Url {
+        // Newsletter issues are delivered through the batch endpoint.
+        let body: serde_json::Value = email_request.body_json().unwrap();
+        let headers = body[0]["Headers"].as_array().expect("No headers found.");
+        let list_unsubscribe = headers
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("No List-Unsubscribe header found.")["Value"]
+            .as_str()
+            .unwrap();
+        let raw_link = list_unsubscribe
+            .strip_prefix('<')
+            .and_then(|l| l.strip_suffix('>'))
+            .unwrap();
+        let mut link = reqwest::Url::parse(raw_link).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+
+    /// The preferences link of the only subscriber.
+    pub async fn get_preferences_link(&self) -> reqwest::Url {
+        let subscriber_id = sqlx::query!("select id from subscriptions")
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap()
+            .id;
+        let raw_link =
+            preferences_link(&self.base_url, subscriber_id, &self.hmac_secret);
+        let mut link = reqwest::Url::parse(&raw_link).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+
+    /// The export and erasure links, in that order, of a data request email.
+    pub fn get_data_links(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> (reqwest::Url, reqwest::Url) {
+        let body: serde_json::Value = email_request.body_json().unwrap();
+        let mut links = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .map(|l| {
+                let mut link = reqwest::Url::parse(l.as_str()).unwrap();
+                link.set_port(Some(self.port)).unwrap();
+                link
+            });
+        (links.next().unwrap(), links.next().unwrap())
+    }
+
     pub fn get_confirmation_links(
         &self,
         email_request: &wiremock::Request,
@@ -139,8 +505,8 @@ impl TestApp {
             link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
@@ -150,3 +516,82 @@ pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
 }
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
+/// The body of a request publishing an issue to the default list.
+pub fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// The same issue, published to `list`.
+pub fn list_newsletter_request_body(list: &str) -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["list"] = list.into();
+    body
+}
+
+/// The status of the only subscriber.
+pub async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+/// Expect `expected` confirmation emails to be sent, and accept them.
+pub async fn mock_confirmation_emails(app: &TestApp, expected: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(expected)
+        .mount(&app.email_server)
+        .await;
+}
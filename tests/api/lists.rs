@@ -0,0 +1,404 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    ConfirmationLinks, TestApp, create_confirmed_subscriber,
+    list_newsletter_request_body, spawn_app,
+};
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn create_list(app: &TestApp, slug: &str) {
+    app.post_admin_lists(&serde_json::json!({
+        "slug": slug,
+        "name": "The weekly digest",
+        "sender_name": "Weekly Digest",
+        "confirmation_subject": "Confirm your weekly digest",
+        "confirmation_message": "Welcome to the weekly digest!",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+/// Subscribe to `list` and return the request of the confirmation email.
+async fn subscribe_to_list(
+    app: &TestApp,
+    list: &str,
+    body: &str,
+) -> wiremock::Request {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_list_subscriptions(list, body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn confirm(links: ConfirmationLinks) {
+    reqwest::get(links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn list_status(app: &TestApp, list: &str) -> Option<String> {
+    sqlx::query!(
+        r#"
+        select m.status
+        from list_subscriptions m
+        join lists l using (list_id)
+        where l.slug = $1
+        "#,
+        list
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| r.status)
+}
+
+#[tokio::test]
+async fn admins_can_create_and_list_mailing_lists() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_lists(&serde_json::json!({
+            "slug": "weekly",
+            "name": "The weekly digest",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(201, response.status().as_u16());
+    let list: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(list["slug"], "weekly");
+    assert_eq!(list["is_default"], false);
+    assert_eq!(
+        list["confirmation_message"],
+        "Welcome to The weekly digest!"
+    );
+
+    let lists: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/api/v1/admin/lists", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let slugs: Vec<_> = lists
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|l| l["slug"].as_str().unwrap())
+        .collect();
+    assert_eq!(slugs, ["newsletter", "weekly"]);
+}
+
+#[tokio::test]
+async fn creating_a_list_rejects_invalid_data_and_taken_slugs() {
+    // Prep
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"slug": "Not a slug", "name": "Weekly"}),
+            400,
+            "invalid slug",
+        ),
+        (
+            serde_json::json!({"slug": "daily", "name": " "}),
+            400,
+            "empty name",
+        ),
+        (
+            serde_json::json!({
+                "slug": "daily",
+                "name": "Daily",
+                "sender_email": "not-an-email",
+            }),
+            400,
+            "invalid sender email",
+        ),
+        (
+            serde_json::json!({"slug": "weekly", "name": "Weekly"}),
+            409,
+            "taken slug",
+        ),
+    ];
+
+    for (body, expected_status, description) in test_cases {
+        // Act
+        let response = app.post_admin_lists(&body).await;
+
+        // Assert
+        assert_eq!(
+            expected_status,
+            response.status().as_u16(),
+            "The API did not reject the list when the payload had an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn creating_a_list_requires_authentication() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/admin/lists", &app.address))
+        .json(&serde_json::json!({"slug": "weekly", "name": "Weekly"}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_404() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_list_subscriptions("no-such-list", SUBSCRIBER.into())
+        .await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "unknown_list");
+}
+
+#[tokio::test]
+async fn each_list_is_confirmed_separately_with_its_own_email() {
+    // Prep
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let request = subscribe_to_list(&app, "weekly", SUBSCRIBER).await;
+
+    // Assert
+    let email: serde_json::Value = request.body_json().unwrap();
+    assert_eq!(email["Subject"], "Confirm your weekly digest");
+    assert!(
+        email["From"]
+            .as_str()
+            .unwrap()
+            .starts_with("\"Weekly Digest\"")
+    );
+    assert!(
+        email["TextBody"]
+            .as_str()
+            .unwrap()
+            .starts_with("Welcome to the weekly digest!")
+    );
+    let links = app.get_confirmation_links(&request);
+    assert_eq!(links.html.path(), "/lists/weekly/subscriptions/confirm");
+    assert_eq!(list_status(&app, "newsletter").await.unwrap(), "confirmed");
+    assert_eq!(
+        list_status(&app, "weekly").await.unwrap(),
+        "pending_confirmation"
+    );
+
+    confirm(links).await;
+    assert_eq!(list_status(&app, "weekly").await.unwrap(), "confirmed");
+}
+
+#[tokio::test]
+async fn a_confirmation_link_only_works_for_its_own_list() {
+    // Prep
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    let request = subscribe_to_list(&app, "weekly", SUBSCRIBER).await;
+    let mut link = app.get_confirmation_links(&request).html;
+
+    // Act
+    link.set_path("/subscriptions/confirm");
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        list_status(&app, "weekly").await.unwrap(),
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn an_issue_published_to_a_list_only_reaches_its_members() {
+    // Prep
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    create_confirmed_subscriber(&app).await;
+    let request = subscribe_to_list(
+        &app,
+        "weekly",
+        "name=tolkien&email=jrr_tolkien%40gmail.com",
+    )
+    .await;
+    confirm(app.get_confirmation_links(&request)).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(list_newsletter_request_body("weekly"))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(202, response.status().as_u16());
+    let requests = app.email_server.received_requests().await.unwrap();
+    let batch: serde_json::Value =
+        requests.last().unwrap().body_json().unwrap();
+    let batch = batch.as_array().unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch[0]["To"], "jrr_tolkien@gmail.com");
+    assert!(
+        batch[0]["From"]
+            .as_str()
+            .unwrap()
+            .starts_with("\"Weekly Digest\"")
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_returns_400() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(list_newsletter_request_body("no-such-list"))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unsubscribing_from_an_issue_only_leaves_its_list() {
+    // Prep
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    create_confirmed_subscriber(&app).await;
+    let request = subscribe_to_list(&app, "weekly", SUBSCRIBER).await;
+    confirm(app.get_confirmation_links(&request)).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(list_newsletter_request_body("weekly"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(requests.last().unwrap());
+
+    // Act
+    let form = reqwest::get(unsubscribe_link.clone())
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(form.contains("Do you want to stop receiving The weekly digest?"));
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(list_status(&app, "weekly").await.unwrap(), "unsubscribed");
+    assert_eq!(list_status(&app, "newsletter").await.unwrap(), "confirmed");
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_can_choose_their_lists_from_the_preferences_page()
+ {
+    // Prep
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act - Part 1 - The lists are offered
+    let html_page = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"name="list.newsletter" value="on" checked"#));
+    assert!(html_page.contains(r#"name="list.weekly" value="on">"#));
+
+    // Act - Part 2 - Swap one list for the other
+    app.api_client
+        .post(link)
+        .form(&serde_json::json!({
+            "name": "le guin",
+            "email_format": "html",
+            "pause_days": "",
+            "shown_lists": "newsletter,weekly",
+            "list.weekly": "on",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(list_status(&app, "weekly").await.unwrap(), "confirmed");
+    assert_eq!(
+        list_status(&app, "newsletter").await.unwrap(),
+        "unsubscribed"
+    );
+}
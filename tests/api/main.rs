@@ -1,6 +1,19 @@
+mod admin_dashboard;
+mod admin_dead_letters;
+mod dev_outbox;
 mod greet;
 mod health_check;
 mod helpers;
+mod lists;
+mod login;
 mod newsletter;
+mod rate_limit;
+mod signup_protection;
 mod subscriptions;
+mod subscriptions_api;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_preferences;
+mod subscriptions_resend;
+mod subscriptions_unsubscribe;
+mod webhooks;
@@ -1,4 +1,8 @@
-use crate::helpers::{ConfirmationLinks, TestApp, spawn_app};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber,
+    newsletter_request_body, spawn_app,
+};
+use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -23,9 +27,10 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
 
     assert_eq!(
-        200,
+        202,
         app.post_newsletters(newsletter_request_body).await.status()
     );
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -34,7 +39,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
@@ -50,9 +55,10 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
 
     assert_eq!(
-        200,
+        202,
         app.post_newsletters(newsletter_request_body).await.status()
     );
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -86,39 +92,417 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "unauthorized");
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
 
-    let _mock_guard = Mock::given(path("/email"))
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn requests_without_an_idempotency_key_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
 
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
+    let response = app
+        .post_newsletters_with_key(
+            newsletter_request_body.clone(),
+            &idempotency_key,
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    // Retry the same request.
+    let response = app
+        .post_newsletters_with_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
 
-    app.get_confirmation_links(&email_request)
+    app.dispatch_all_pending_emails().await;
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
+#[tokio::test]
+async fn concurrent_requests_are_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    reqwest::get(confirmation_links.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response1 = app.post_newsletters_with_key(
+        newsletter_request_body.clone(),
+        &idempotency_key,
+    );
+    let response2 = app
+        .post_newsletters_with_key(newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn publishing_returns_before_the_issue_is_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(202, response.status().as_u16());
+
+    let queued =
+        sqlx::query!("select count(*) as count from issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.count, Some(1));
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried_later() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        "select n_retries, execute_after > now() as delayed
+        from issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 1);
+    assert_eq!(task.delayed, Some(true));
+
+    let dead_letters = sqlx::query!(
+        "select count(*) as count from issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(dead_letters.count, Some(0));
+}
+
+#[tokio::test]
+async fn permanent_delivery_failures_are_dead_lettered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let dead_letter = sqlx::query!(
+        "select subscriber_email, n_attempts from issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(dead_letter.n_attempts, 1);
+
+    let queued =
+        sqlx::query!("select count(*) as count from issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.count, Some(0));
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_once_retries_are_exhausted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    for _ in 0..5 {
+        app.dispatch_all_pending_emails().await;
+        // Skip the backoff delay.
+        sqlx::query!("update issue_delivery_queue set execute_after = now()")
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    let dead_letter =
+        sqlx::query!("select n_attempts from issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(dead_letter.n_attempts, 5);
+}
+
+#[tokio::test]
+async fn successful_deliveries_record_the_provider_message_id() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!([{
+                "ErrorCode": 0,
+                "Message": "OK",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                "SubmittedAt": "2025-03-27T23:15:33.175091Z",
+                "To": "ursula_le_guin@gmail.com"
+            }]),
+        ))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!(
+        "select subscriber_email, provider_message_id from issue_deliveries"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(
+        delivery.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+}
+
+#[tokio::test]
+async fn partial_batch_failures_are_recorded_per_delivery() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let inactive_id = Uuid::new_v4();
+    sqlx::query!(
+        "insert into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status
+        )
+        values (
+            $1, 'inactive@example.com', 'inactive@example.com', 'inactive',
+            now(), 'confirmed'
+        )",
+        inactive_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "insert into list_subscriptions (
+            list_id, subscriber_id, status, subscribed_at
+        )
+        select list_id, $1, 'confirmed', now() from lists where is_default",
+        inactive_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(move |request: &wiremock::Request| {
+            let messages: Vec<serde_json::Value> = request.body_json().unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|m| {
+                    if m["To"] == "inactive@example.com" {
+                        serde_json::json!({
+                            "ErrorCode": 406,
+                            "Message": "Inactive recipient"
+                        })
+                    } else {
+                        serde_json::json!({
+                            "ErrorCode": 0,
+                            "Message": "OK",
+                            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"
+                        })
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery =
+        sqlx::query!("select subscriber_email from issue_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(delivery.subscriber_email, "ursula_le_guin@gmail.com");
+    let dead_letter = sqlx::query!(
+        "select subscriber_email, last_error from issue_delivery_dead_letters"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(dead_letter.subscriber_email, "inactive@example.com");
+    assert!(dead_letter.last_error.contains("Inactive recipient"));
 }
@@ -0,0 +1,120 @@
+use zero2prod::configuration::{RateLimitBackend, Settings};
+
+use crate::helpers::{mock_confirmation_emails, spawn_app_with};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+fn one_email_per_address(c: &mut Settings) {
+    c.application.rate_limit.per_email.capacity = 1;
+    c.application.rate_limit.per_email.refill_interval_seconds = 3600;
+}
+
+async fn assert_is_rate_limited(response: reqwest::Response) {
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "too_many_requests");
+}
+
+#[tokio::test]
+async fn subscribing_the_same_address_too_often_is_rejected_with_a_429() {
+    // Prep
+    let app = spawn_app_with(one_email_per_address).await;
+    mock_confirmation_emails(&app, 1).await;
+
+    // Act
+    let first = app.post_subscriptions(BODY.into()).await;
+    let second = app
+        .post_subscriptions(
+            "name=le%20guin&email=URSULA_LE_GUIN%40gmail.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_is_rate_limited(second).await;
+}
+
+#[tokio::test]
+async fn the_per_address_limit_is_shared_with_the_api_and_resend() {
+    // Prep
+    let app = spawn_app_with(one_email_per_address).await;
+    mock_confirmation_emails(&app, 1).await;
+    app.post_subscriptions(BODY.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let api = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+    let resend = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_is_rate_limited(api).await;
+    assert_is_rate_limited(resend).await;
+}
+
+#[tokio::test]
+async fn too_many_requests_from_one_client_are_rejected_with_a_429() {
+    // Prep
+    let app = spawn_app_with(|c| {
+        c.application.rate_limit.per_ip.capacity = 2;
+        c.application.rate_limit.per_ip.refill_interval_seconds = 3600;
+    })
+    .await;
+    mock_confirmation_emails(&app, 2).await;
+
+    // Act
+    let mut responses = Vec::new();
+    for name in ["a", "b", "c"] {
+        let body = format!("name={}&email={}%40gmail.com", name, name);
+        responses.push(app.post_subscriptions(body).await);
+    }
+
+    // Assert
+    let last = responses.pop().unwrap();
+    for response in responses {
+        assert_eq!(200, response.status().as_u16());
+    }
+    assert_is_rate_limited(last).await;
+}
+
+#[tokio::test]
+async fn the_postgres_backend_enforces_the_same_limits() {
+    // Prep
+    let app = spawn_app_with(|c| {
+        one_email_per_address(c);
+        c.application.rate_limit.backend = RateLimitBackend::Postgres;
+    })
+    .await;
+    mock_confirmation_emails(&app, 1).await;
+
+    // Act
+    let first = app.post_subscriptions(BODY.into()).await;
+    let second = app.post_subscriptions(BODY.into()).await;
+
+    // Assert
+    assert_eq!(200, first.status().as_u16());
+    assert_is_rate_limited(second).await;
+    let keys: Vec<String> =
+        sqlx::query!("select key from rate_limit_buckets order by key")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.key)
+            .collect();
+    assert_eq!(keys, ["email:ursula_le_guin@gmail.com", "ip:127.0.0.1"]);
+}
@@ -0,0 +1,215 @@
+use zero2prod::{configuration::Settings, signup_protection::is_solution};
+
+use crate::helpers::{
+    TestApp, mock_confirmation_emails, spawn_app, spawn_app_with,
+};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+fn require_proof_of_work(c: &mut Settings) {
+    c.application.proof_of_work.enabled = true;
+    c.application.proof_of_work.difficulty = 8;
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"select count(*) as "count!" from subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+/// Fetch a challenge and brute force it, as a browser would.
+async fn solve_challenge(app: &TestApp) -> (String, String) {
+    let response: serde_json::Value =
+        app.get_challenge().await.json().await.unwrap();
+    let challenge = response["challenge"].as_str().unwrap().to_owned();
+    let difficulty = response["difficulty"].as_u64().unwrap() as u32;
+    let solution = (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| is_solution(&challenge, solution, difficulty))
+        .unwrap();
+    (challenge, solution)
+}
+
+fn body_with(form: &str, challenge: &str, solution: &str) -> String {
+    format!(
+        "{}&{}",
+        form,
+        serde_urlencoded::to_string([
+            ("pow_challenge", challenge),
+            ("pow_solution", solution),
+        ])
+        .unwrap()
+    )
+}
+
+async fn assert_is_invalid_proof_of_work(response: reqwest::Response) {
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "invalid_proof_of_work");
+}
+
+#[tokio::test]
+async fn filling_in_the_honeypot_pretends_to_subscribe() {
+    // Prep
+    let app = spawn_app().await;
+    mock_confirmation_emails(&app, 0).await;
+
+    // Act
+    let form = app
+        .post_subscriptions(format!("{}&website=spam.example.com", BODY))
+        .await;
+    let api = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "website": "spam.example.com"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, form.status().as_u16());
+    assert_eq!(202, api.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn challenges_are_issued_with_their_difficulty() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+
+    // Act
+    let response = app.get_challenge().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["challenge"].is_string());
+    assert_eq!(body["difficulty"], 8);
+    assert!(body["expires_at"].is_string());
+}
+
+#[tokio::test]
+async fn subscribing_without_a_solved_challenge_is_rejected() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+    mock_confirmation_emails(&app, 0).await;
+
+    // Act
+    let response = app.post_subscriptions(BODY.into()).await;
+
+    // Assert
+    assert_is_invalid_proof_of_work(response).await;
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn subscribing_with_a_solved_challenge_is_accepted() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+    mock_confirmation_emails(&app, 1).await;
+    let (challenge, solution) = solve_challenge(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(body_with(BODY, &challenge, &solution))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn the_api_requires_a_solved_challenge_too() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+    mock_confirmation_emails(&app, 1).await;
+    let (challenge, solution) = solve_challenge(&app).await;
+
+    // Act
+    let missing = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+    let solved = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "pow_challenge": challenge,
+            "pow_solution": solution
+        }))
+        .await;
+
+    // Assert
+    assert_is_invalid_proof_of_work(missing).await;
+    assert_eq!(202, solved.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_wrong_solution_is_rejected() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+    mock_confirmation_emails(&app, 0).await;
+    let (challenge, _) = solve_challenge(&app).await;
+    let wrong = (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| !is_solution(&challenge, solution, 8))
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(body_with(BODY, &challenge, &wrong))
+        .await;
+
+    // Assert
+    assert_is_invalid_proof_of_work(response).await;
+}
+
+#[tokio::test]
+async fn a_forged_challenge_is_rejected() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+    mock_confirmation_emails(&app, 0).await;
+    let challenge = "00112233445566778899aabbccddeeff.4102444800.00";
+    let solution = (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| is_solution(challenge, solution, 8))
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(body_with(BODY, challenge, &solution))
+        .await;
+
+    // Assert
+    assert_is_invalid_proof_of_work(response).await;
+}
+
+#[tokio::test]
+async fn a_solved_challenge_cannot_be_replayed() {
+    // Prep
+    let app = spawn_app_with(require_proof_of_work).await;
+    mock_confirmation_emails(&app, 1).await;
+    let (challenge, solution) = solve_challenge(&app).await;
+    app.post_subscriptions(body_with(BODY, &challenge, &solution))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(body_with(
+            "name=tolkien&email=tolkien%40gmail.com",
+            &challenge,
+            &solution,
+        ))
+        .await;
+
+    // Assert
+    assert_is_invalid_proof_of_work(response).await;
+    assert_eq!(subscriber_count(&app).await, 1);
+}
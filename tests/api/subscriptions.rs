@@ -1,9 +1,14 @@
+use uuid::Uuid;
 use wiremock::{
     Mock, ResponseTemplate,
     matchers::{method, path},
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::email_normalization::normalize_stored_emails;
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -165,3 +170,413 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(result.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_again_while_pending_resends_the_confirmation_email() {
+    // Prep
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let old_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!(
+        "update subscription_tokens
+        set created_at = created_at - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(new_links.html, old_links.html);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribing_again_right_away_does_not_send_another_email() {
+    // Prep
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn addresses_differing_only_in_case_are_the_same_subscriber() {
+    // Prep
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=%20Ursula_Le_Guin%40GMAIL.com%20".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved =
+        sqlx::query!("select email, email_normalized from subscriptions")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved[0].email_normalized, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_succeeds_silently() {
+    // Prep
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_restarts_double_opt_in() {
+    // Prep
+    let app = spawn_app().await;
+    let body = "name=Ursula%20K.&email=ursula_le_guin%40gmail.com";
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("update subscriptions set status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K.");
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_only_stores_a_hash_of_the_confirmation_token() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    // Assert
+    let token = confirmation_links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+    assert!(token.len() >= 25);
+    assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+    let saved =
+        sqlx::query!("SELECT subscription_token_hash FROM subscription_tokens")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_ne!(saved.subscription_token_hash, token);
+    assert!(!saved.subscription_token_hash.contains(&token));
+}
+
+#[tokio::test]
+async fn validation_errors_are_reported_field_by_field() {
+    // Prep
+    let app = spawn_app().await;
+    let body = "name=%3Cscript%3E&email=definitely-not-an-email";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let error = &body["error"];
+    assert_eq!(error["code"], "validation_error");
+    assert!(
+        error["request_id"]
+            .as_str()
+            .is_some_and(|id| !id.is_empty())
+    );
+    let fields: Vec<&str> = error["details"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["name", "email"]);
+    assert!(
+        error["details"][1]["message"]
+            .as_str()
+            .unwrap()
+            .contains("definitely-not-an-email")
+    );
+}
+
+#[tokio::test]
+async fn typos_in_common_domains_are_rejected_with_a_suggestion() {
+    // Prep
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmial.com";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let detail = &body["error"]["details"][0];
+    assert_eq!(detail["field"], "email");
+    assert!(
+        detail["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Did you mean ursula_le_guin@gmail.com?")
+    );
+}
+
+#[tokio::test]
+async fn near_misses_of_common_domains_can_be_kept_as_typed() {
+    // Prep
+    let app = spawn_app().await;
+    // A real domain, one edit away from `me.com`.
+    let body = "name=jack&email=jack%40ge.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let suggested = app.post_subscriptions(body.into()).await;
+    let kept = app
+        .post_subscriptions(format!("{}&keep_email=true", body))
+        .await;
+
+    // Assert
+    assert_eq!(400, suggested.status().as_u16());
+    assert_eq!(200, kept.status().as_u16());
+    let saved = sqlx::query!("select email from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "jack@ge.com");
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected() {
+    // Prep
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40mailinator.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "validation_error");
+    assert_eq!(body["error"]["details"][0]["field"], "email");
+}
+
+#[tokio::test]
+async fn malformed_forms_are_reported_in_the_error_envelope() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "bad_request");
+    assert!(body["error"]["message"].as_str().unwrap().contains("email"));
+    assert_eq!(body["error"]["details"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn unexpected_errors_do_not_leak_details() {
+    // Prep
+    let app = spawn_app().await;
+    sqlx::query!("alter table subscriptions drop column email;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "internal_error");
+    assert_eq!(body["error"]["message"], "An unexpected error occurred.");
+}
+
+#[tokio::test]
+async fn stored_internationalised_addresses_are_normalized_like_new_ones() {
+    // Prep
+    let app = spawn_app().await;
+    // As left by the migration: the domain was only lowercased.
+    let migrated_id = Uuid::new_v4();
+    sqlx::query!(
+        "insert into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status
+        )
+        values (
+            $1, 'Ursula@Bücher.example', 'ursula@bücher.example', 'ursula',
+            now() - interval '1 day', 'confirmed'
+        )",
+        migrated_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // The same address, subscribed again since then.
+    sqlx::query!(
+        "insert into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status
+        )
+        values (
+            $1, 'ursula@bücher.example', 'ursula@xn--bcher-kva.example',
+            'ursula', now(), 'pending_confirmation'
+        )",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Only the domain is punycode-encoded: this one is already normalized.
+    sqlx::query!(
+        "insert into subscriptions (
+            id, email, email_normalized, name, subscribed_at, status
+        )
+        values (
+            $1, 'José@example.com', 'josé@example.com', 'jose', now(),
+            'confirmed'
+        )",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let first_run = normalize_stored_emails(&app.db_pool).await.unwrap();
+    let second_run = normalize_stored_emails(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!((first_run, second_run), (1, 0));
+    let saved = sqlx::query!(
+        "select id, email_normalized from subscriptions order by email_normalized"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[1].id, migrated_id);
+    assert_eq!(saved[1].email_normalized, "ursula@xn--bcher-kva.example");
+    assert_eq!(saved[0].email_normalized, "josé@example.com");
+}
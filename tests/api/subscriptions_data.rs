@@ -0,0 +1,394 @@
+use chrono::{TimeDelta, Utc};
+use sha2::{Digest, Sha256};
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+
+use zero2prod::subscriber_token::{data_erasure_link, data_export_link};
+
+use crate::helpers::{TestApp, create_confirmed_subscriber, spawn_app};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+/// Ask for the data links of the test subscriber and return them.
+async fn request_data_links(app: &TestApp) -> (reqwest::Url, reqwest::Url) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_data_request("email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_data_links(&email_request)
+}
+
+/// Publish an issue and deliver it to the test subscriber.
+async fn deliver_an_issue(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn held_rows(app: &TestApp) -> (i64, i64, i64) {
+    let row = sqlx::query!(
+        r#"
+        select
+            (select count(*) from subscriptions) as "subscriptions!",
+            (select count(*) from subscription_tokens) as "tokens!",
+            (select count(*) from issue_deliveries) as "deliveries!"
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    (row.subscriptions, row.tokens, row.deliveries)
+}
+
+#[tokio::test]
+async fn unknown_addresses_get_the_same_answer_but_no_email() {
+    // Prep
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_data_request("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("If we hold any data about that address")
+    );
+}
+
+#[tokio::test]
+async fn known_addresses_get_the_same_answer_when_the_email_cannot_be_sent() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_data_request("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response
+            .text()
+            .await
+            .unwrap()
+            .contains("If we hold any data about that address")
+    );
+}
+
+#[tokio::test]
+async fn data_links_expire_after_a_day() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("select id from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let issued_at = (Utc::now() - TimeDelta::hours(25)).timestamp();
+    let link = |raw_link: String| {
+        let mut link = reqwest::Url::parse(&raw_link).unwrap();
+        link.set_port(Some(app.port)).unwrap();
+        link
+    };
+    let export_link = link(data_export_link(
+        &app.base_url,
+        subscriber_id,
+        issued_at,
+        &app.hmac_secret,
+    ));
+    let erasure_link = link(data_erasure_link(
+        &app.base_url,
+        subscriber_id,
+        issued_at,
+        &app.hmac_secret,
+    ));
+
+    // Act
+    let export = reqwest::get(export_link).await.unwrap();
+    let erasure_form = reqwest::get(erasure_link.clone()).await.unwrap();
+    let erasure = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(export.status().as_u16(), 401);
+    let body: serde_json::Value = export.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "expired_token");
+    assert_eq!(erasure_form.status().as_u16(), 401);
+    assert_eq!(erasure.status().as_u16(), 401);
+    assert_eq!(held_rows(&app).await.0, 1);
+}
+
+#[tokio::test]
+async fn the_export_link_returns_everything_held_about_the_subscriber() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    deliver_an_issue(&app).await;
+    let (export_link, _) = request_data_links(&app).await;
+
+    // Act
+    let response = reqwest::get(export_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        response.headers()["Content-Disposition"]
+            .to_str()
+            .unwrap()
+            .starts_with("attachment")
+    );
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["email"], EMAIL);
+    assert_eq!(export["subscription"]["name"], "le guin");
+    assert_eq!(export["subscription"]["status"], "confirmed");
+    assert_eq!(export["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(export["deliveries"][0]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn a_data_link_with_a_tampered_token_is_rejected() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (export_link, erasure_link) = request_data_links(&app).await;
+
+    // Act
+    let [export_link, erasure_link] =
+        [export_link, erasure_link].map(|mut link| {
+            let pairs: Vec<(String, String)> = link
+                .query_pairs()
+                .filter(|(k, _)| k != "token")
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect();
+            link.query_pairs_mut()
+                .clear()
+                .extend_pairs(pairs)
+                .append_pair("token", &"0".repeat(64));
+            link
+        });
+    let responses = [
+        reqwest::get(export_link).await.unwrap(),
+        reqwest::get(erasure_link.clone()).await.unwrap(),
+        reqwest::Client::new()
+            .post(erasure_link)
+            .send()
+            .await
+            .unwrap(),
+    ];
+
+    // Assert
+    for response in responses {
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    assert_eq!(held_rows(&app).await.0, 1);
+}
+
+#[tokio::test]
+async fn following_the_erasure_link_does_not_erase_anything() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, erasure_link) = request_data_links(&app).await;
+
+    // Act
+    let html_page = reqwest::get(erasure_link)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("Erase my data"));
+    assert_eq!(held_rows(&app).await, (1, 1, 0));
+}
+
+#[tokio::test]
+async fn erasing_removes_the_subscriber_their_tokens_and_their_deliveries() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    deliver_an_issue(&app).await;
+    let (export_link, erasure_link) = request_data_links(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(held_rows(&app).await, (0, 0, 0));
+    let suppressed = sqlx::query!("select email_hash from suppressed_emails")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressed.len(), 1);
+    assert_ne!(suppressed[0].email_hash, EMAIL);
+    // Keyed, so that known addresses cannot be matched against it.
+    assert_ne!(
+        suppressed[0].email_hash,
+        hex::encode(Sha256::digest(EMAIL.as_bytes()))
+    );
+    let export = reqwest::get(export_link).await.unwrap();
+    assert_eq!(export.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_erased_address_cannot_be_signed_up_again() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (_, erasure_link) = request_data_links(&app).await;
+    reqwest::Client::new()
+        .post(erasure_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(held_rows(&app).await.0, 0);
+}
+
+#[tokio::test]
+async fn the_admin_api_requires_authentication() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let export = reqwest::Client::new()
+        .post(format!(
+            "{}/api/v1/admin/subscriber_data/export",
+            &app.address
+        ))
+        .json(&serde_json::json!({"email": EMAIL}))
+        .send()
+        .await
+        .unwrap();
+    let erase = reqwest::Client::new()
+        .post(format!(
+            "{}/api/v1/admin/subscriber_data/erase",
+            &app.address
+        ))
+        .json(&serde_json::json!({"email": EMAIL}))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(export.status().as_u16(), 401);
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        export.headers()["WWW-Authenticate"]
+    );
+    assert_eq!(erase.status().as_u16(), 401);
+    assert_eq!(held_rows(&app).await.0, 1);
+}
+
+#[tokio::test]
+async fn the_admin_api_exports_and_erases_an_address() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    deliver_an_issue(&app).await;
+
+    // Act - Part 1 - Export
+    let response = app
+        .post_admin_subscriber_data_export("Ursula_Le_Guin@Gmail.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscription"]["email"], EMAIL);
+    assert_eq!(export["deliveries"].as_array().unwrap().len(), 1);
+
+    // Act - Part 2 - Erase
+    let response = app.post_admin_subscriber_data_erase(EMAIL).await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(held_rows(&app).await, (0, 0, 0));
+
+    // Act - Part 3 - Nothing is left to export
+    let response = app.post_admin_subscriber_data_export(EMAIL).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_admin_api_rejects_invalid_addresses() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_admin_subscriber_data_export("not-an-email").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
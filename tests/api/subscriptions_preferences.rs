@@ -0,0 +1,260 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+
+use crate::helpers::{
+    TestApp, assert_is_redirect_to, create_confirmed_subscriber,
+    create_unconfirmed_subscriber, newsletter_request_body, spawn_app,
+};
+
+struct SavedPreferences {
+    name: String,
+    email_format: String,
+    paused: bool,
+}
+
+async fn saved_preferences(app: &TestApp) -> SavedPreferences {
+    sqlx::query_as!(
+        SavedPreferences,
+        r#"
+        select name, email_format, paused_until is not null as "paused!"
+        from subscriptions
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn post_preferences(
+    app: &TestApp,
+    link: &reqwest::Url,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(link.clone())
+        .form(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_preferences_html(app: &TestApp, link: &reqwest::Url) -> String {
+    app.api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_preferences_page_shows_the_current_preferences() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act
+    let html_page = get_preferences_html(&app, &link).await;
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains(r#"value="le&#x20;guin""#));
+    assert!(html_page.contains(r#"value="html" checked"#));
+}
+
+#[tokio::test]
+async fn a_tampered_token_is_rejected_with_a_401() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut link = app.get_preferences_link().await;
+    let subscriber_id = link
+        .query_pairs()
+        .find(|(k, _)| k == "subscriber_id")
+        .unwrap()
+        .1
+        .into_owned();
+    link.query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("token", &"0".repeat(64));
+
+    // Act
+    let page = app.api_client.get(link.clone()).send().await.unwrap();
+    let update = post_preferences(
+        &app,
+        &link,
+        &serde_json::json!({"name": "mallory", "email_format": "text"}),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 401);
+    assert_eq!(update.status().as_u16(), 401);
+    assert_eq!(saved_preferences(&app).await.name, "le guin");
+}
+
+#[tokio::test]
+async fn preferences_can_be_updated() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act - Part 1 - Save
+    let response = post_preferences(
+        &app,
+        &link,
+        &serde_json::json!({
+            "name": "Ursula K. Le Guin",
+            "email_format": "text",
+            "pause_days": "30",
+        }),
+    )
+    .await;
+
+    // Assert
+    let page_path = format!("{}?{}", link.path(), link.query().unwrap());
+    assert_is_redirect_to(&response, &page_path);
+    let saved = saved_preferences(&app).await;
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.email_format, "text");
+    assert!(saved.paused);
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_preferences_html(&app, &link).await;
+    assert!(html_page.contains("Your preferences have been saved."));
+    assert!(html_page.contains("Stay paused until"));
+}
+
+#[tokio::test]
+async fn an_invalid_name_is_reported_and_nothing_is_saved() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act
+    post_preferences(
+        &app,
+        &link,
+        &serde_json::json!({"name": "<script>", "email_format": "text"}),
+    )
+    .await;
+
+    // Assert
+    let html_page = get_preferences_html(&app, &link).await;
+    assert!(html_page.contains("is not a valid subscriber name."));
+    let saved = saved_preferences(&app).await;
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.email_format, "html");
+}
+
+#[tokio::test]
+async fn plain_text_subscribers_receive_no_html_body() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    post_preferences(
+        &app,
+        &link,
+        &serde_json::json!({"name": "le guin", "email_format": "text"}),
+    )
+    .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = email_request.body_json().unwrap();
+    assert_eq!(body[0]["TextBody"], "Newsletter body as plain text");
+    assert!(body[0].get("HtmlBody").is_none());
+}
+
+#[tokio::test]
+async fn paused_subscribers_receive_nothing_until_they_resume() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+    post_preferences(
+        &app,
+        &link,
+        &serde_json::json!({
+            "name": "le guin",
+            "email_format": "html",
+            "pause_days": "7",
+        }),
+    )
+    .await;
+
+    // Act - Part 1 - Publish while paused
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    // Act - Part 2 - Resume, then publish again
+    post_preferences(
+        &app,
+        &link,
+        &serde_json::json!({
+            "name": "le guin",
+            "email_format": "html",
+            "pause_days": "0",
+        }),
+    )
+    .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_confirmation_page_links_to_the_preferences() {
+    // Prep
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let link = app.get_preferences_link().await;
+
+    // Act
+    let html_page = reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    let page_path = format!("{}?{}", link.path(), link.query().unwrap());
+    assert!(html_page.contains(&htmlescape::encode_attribute(&page_path)));
+}
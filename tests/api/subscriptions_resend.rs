@@ -0,0 +1,133 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+
+use crate::helpers::{
+    TestApp, create_confirmed_subscriber, create_unconfirmed_subscriber,
+    spawn_app,
+};
+
+const BODY: &str = "email=ursula_le_guin%40gmail.com";
+
+/// Pretend the last confirmation email went out long enough ago.
+async fn wait_out_the_cooldown(app: &TestApp) {
+    sqlx::query!(
+        "update subscription_tokens
+        set created_at = created_at - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn resending_sends_a_new_link_and_revokes_the_old_one() {
+    // Prep
+    let app = spawn_app().await;
+    let old_links = create_unconfirmed_subscriber(&app).await;
+    wait_out_the_cooldown(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_resend_confirmation(BODY.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(new_links.html, old_links.html);
+
+    let response = reqwest::get(old_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("select status from subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn resending_within_the_cooldown_looks_like_resending_to_an_unknown_address()
+ {
+    // Prep
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let pending = app.post_resend_confirmation(BODY.into()).await;
+    let unknown = app
+        .post_resend_confirmation("email=someone_else%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(pending.status().as_u16(), 200);
+    assert_eq!(unknown.status().as_u16(), 200);
+    assert!(pending.headers().get("Retry-After").is_none());
+    assert_eq!(pending.text().await.unwrap(), unknown.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn resending_to_an_unknown_or_confirmed_address_sends_nothing() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    wait_out_the_cooldown(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        (BODY, "a confirmed subscriber"),
+        ("email=someone_else%40gmail.com", "an unknown address"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_resend_confirmation(body.into()).await;
+
+        // Assert
+        assert_eq!(
+            200,
+            response.status().as_u16(),
+            "The API did not return a 200 for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn resending_to_an_invalid_address_is_rejected_with_a_400() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=definitely-not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,171 @@
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{any, method, path},
+};
+
+use crate::helpers::{
+    TestApp, create_confirmed_subscriber, newsletter_request_body, spawn_app,
+    subscriber_status,
+};
+
+/// Publish an issue to a freshly confirmed subscriber and return the
+/// unsubscribe link it carried.
+async fn receive_unsubscribe_link(app: &TestApp) -> reqwest::Url {
+    create_confirmed_subscriber(app).await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_unsubscribe_link(&email_request)
+}
+
+#[tokio::test]
+async fn newsletter_issues_carry_one_click_unsubscribe_headers() {
+    let app = spawn_app().await;
+
+    receive_unsubscribe_link(&app).await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = email_request.body_json().unwrap();
+    assert!(
+        body[0]["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|h| h["Name"] == "List-Unsubscribe-Post"
+                && h["Value"] == "List-Unsubscribe=One-Click")
+    );
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_does_not_unsubscribe() {
+    let app = spawn_app().await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("<form"));
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_one_click_unsubscribe_request_unsubscribes() {
+    let app = spawn_app().await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    let app = spawn_app().await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn pending_deliveries_are_skipped_after_unsubscribing() {
+    let app = spawn_app().await;
+    let unsubscribe_link = receive_unsubscribe_link(&app).await;
+
+    // Enqueue before unsubscribing, deliver after.
+    app.post_newsletters(newsletter_request_body()).await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_tampered_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+    let mut unsubscribe_link = receive_unsubscribe_link(&app).await;
+    let subscriber_id = unsubscribe_link
+        .query_pairs()
+        .find(|(k, _)| k == "subscriber_id")
+        .unwrap()
+        .1
+        .into_owned();
+    unsubscribe_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("token", &"0".repeat(64));
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribing_without_a_token_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
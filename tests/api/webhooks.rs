@@ -0,0 +1,215 @@
+use uuid::Uuid;
+use wiremock::{
+    Mock, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use crate::helpers::{
+    create_confirmed_subscriber, spawn_app, subscriber_status,
+};
+
+fn hard_bounce() -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807u64,
+        "Type": "HardBounce",
+        "TypeCode": 1,
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Email": "ursula_le_guin@gmail.com",
+        "BouncedAt": "2025-05-20T16:09:19Z"
+    })
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Prep
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email-provider", &app.address))
+        .json(&hard_bounce())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="webhooks""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn requests_with_the_wrong_password_are_rejected() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email-provider", &app.address))
+        .basic_auth(
+            &app.webhook_settings.username,
+            Some(Uuid::new_v4().to_string()),
+        )
+        .json(&hard_bounce())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn a_hard_bounce_deactivates_the_subscriber() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app.post_email_provider_webhook(&hard_bounce()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app).await, "bounced");
+    let event = sqlx::query!(
+        "select record_type, provider_message_id, recipient
+        from email_provider_events"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(
+        event.provider_message_id.as_deref(),
+        Some("883953f4-6105-42a2-a16a-77a8eac79483")
+    );
+    assert_eq!(event.recipient.as_deref(), Some("ursula_le_guin@gmail.com"));
+}
+
+#[tokio::test]
+async fn a_soft_bounce_keeps_the_subscriber_confirmed() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut soft_bounce = hard_bounce();
+    soft_bounce["Type"] = "SoftBounce".into();
+    soft_bounce["TypeCode"] = 4096.into();
+
+    // Act
+    let response = app.post_email_provider_webhook(&soft_bounce).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn complaining_subscribers_no_longer_receive_newsletters() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let complaint = serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Email": "ursula_le_guin@gmail.com",
+        "BouncedAt": "2025-05-20T16:09:19Z"
+    });
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_email_provider_webhook(&complaint).await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn delivery_events_are_recorded() {
+    // Prep
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let delivery = serde_json::json!({
+        "RecordType": "Delivery",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Recipient": "ursula_le_guin@gmail.com",
+        "DeliveredAt": "2025-05-20T16:09:19Z"
+    });
+
+    // Act
+    let response = app.post_email_provider_webhook(&delivery).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    let event = sqlx::query!("select record_type from email_provider_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.record_type, "Delivery");
+}
+
+#[tokio::test]
+async fn other_record_types_are_accepted_and_ignored() {
+    // Prep
+    let app = spawn_app().await;
+    let open = serde_json::json!({
+        "RecordType": "Open",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Recipient": "ursula_le_guin@gmail.com"
+    });
+
+    // Act
+    let response = app.post_email_provider_webhook(&open).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn malformed_events_are_rejected_with_a_400() {
+    // Prep
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "Email": "a@b.com" }),
+            "missing record type",
+        ),
+        (
+            serde_json::json!({ "RecordType": "Bounce", "Type": "HardBounce" }),
+            "bounce without an email",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_email_provider_webhook(&body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 when the payload was {}.",
+            description
+        );
+    }
+}
@@ -1,15 +1,72 @@
-use zero2prod::configuration::ApplicationSettings;
+use zero2prod::configuration::{
+    ApplicationSettings, EmailClientSettings, EmailProvider, SmtpTls,
+};
 
 #[test]
 fn application_settings_port_from_str() {
-    let s = r#" { "port": "123", "host": "Host", "base_url": "127.0.0.1" } "#;
+    let s = r#" { "port": "123", "host": "Host", "base_url": "127.0.0.1",
+        "hmac_secret": "secret", "session_ttl_minutes": 60 } "#;
     let a: ApplicationSettings = serde_json::from_str(s).unwrap();
     assert_eq!(a.port, 123);
 }
 
 #[test]
 fn application_settings_port_from_int() {
-    let s = r#" { "port": 444 , "host": "Host", "base_url": "127.0.0.1" } "#;
+    let s = r#" { "port": 444 , "host": "Host", "base_url": "127.0.0.1",
+        "hmac_secret": "secret", "session_ttl_minutes": 60 } "#;
     let a: ApplicationSettings = serde_json::from_str(s).unwrap();
     assert_eq!(a.port, 444);
 }
+
+#[test]
+fn application_settings_confirmation_redirect_url_is_optional() {
+    let s = r#" { "port": 444 , "host": "Host", "base_url": "127.0.0.1",
+        "hmac_secret": "secret", "session_ttl_minutes": 60 } "#;
+    let a: ApplicationSettings = serde_json::from_str(s).unwrap();
+    assert_eq!(a.confirmation_redirect_url, None);
+
+    let s = r#" { "port": 444 , "host": "Host", "base_url": "127.0.0.1",
+        "hmac_secret": "secret", "session_ttl_minutes": 60,
+        "confirmation_redirect_url": "https://example.com/thanks" } "#;
+    let a: ApplicationSettings = serde_json::from_str(s).unwrap();
+    assert_eq!(
+        a.confirmation_redirect_url.as_deref(),
+        Some("https://example.com/thanks")
+    );
+}
+
+#[test]
+fn email_client_settings_provider_is_parsed() {
+    let s = r#" { "provider": "postmark", "base_url": "https://api.postmark.com",
+        "sender_email": "test@gmail.com", "authorization_token": "token",
+        "timeout_milliseconds": 100,
+        "webhook": { "username": "postmark", "password": "secret" } } "#;
+    let e: EmailClientSettings = serde_json::from_str(s).unwrap();
+    assert_eq!(e.provider, EmailProvider::Postmark);
+}
+
+#[test]
+fn email_client_settings_reject_unknown_providers() {
+    let s = r#" { "provider": "pigeon", "base_url": "https://api.postmark.com",
+        "sender_email": "test@gmail.com", "authorization_token": "token",
+        "timeout_milliseconds": 100,
+        "webhook": { "username": "postmark", "password": "secret" } } "#;
+    assert!(serde_json::from_str::<EmailClientSettings>(s).is_err());
+}
+
+#[test]
+fn email_client_settings_smtp_section_is_parsed() {
+    let s = r#" { "provider": "smtp", "base_url": "localhost",
+        "sender_email": "test@gmail.com", "authorization_token": "token",
+        "timeout_milliseconds": 100,
+        "webhook": { "username": "postmark", "password": "secret" },
+        "smtp": { "host": "smtp.example.com", "port": "465",
+            "tls": "implicit", "username": "user", "password": "pass",
+            "pool_max_size": 4 } } "#;
+    let e: EmailClientSettings = serde_json::from_str(s).unwrap();
+    assert_eq!(e.provider, EmailProvider::Smtp);
+    let smtp = e.smtp.unwrap();
+    assert_eq!(smtp.port, 465);
+    assert_eq!(smtp.tls, SmtpTls::Implicit);
+    assert_eq!(smtp.username.as_deref(), Some("user"));
+}